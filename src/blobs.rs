@@ -0,0 +1,60 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Content-addressed storage for `storage_layout = "ContentAddressed"`: each
+/// distinct dump is kept once in `blob_dir` as `<content hash>.<extension>`,
+/// and backup entries are hard links to their blob. Entries stay ordinary
+/// files, so restore, inspect and uploads work as with the flat layout, and a
+/// blob's link count tells whether anything still refers to it.
+///
+/// Stores the backup at `path`: it becomes the blob for `hash` if there is
+/// none yet, otherwise `path` is replaced by a link to the existing blob,
+/// freeing its own copy.
+pub fn intern(blob_dir: &Path, path: &Path, hash: &str, extension: &str) -> Result<()> {
+    fs::create_dir_all(blob_dir)
+        .with_context(|| format!("Failed to create blob directory {:?}", blob_dir))?;
+    let blob = blob_dir.join(format!("{}.{}", hash, extension));
+    match fs::hard_link(path, &blob) {
+        Ok(()) => return Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => {}
+        Err(e) => {
+            return Err(e).with_context(|| format!("Failed to link {:?} into {:?}", path, blob_dir))
+        }
+    }
+
+    // Link next to the entry first, so a failure leaves the entry as it was
+    let mut temp = path.as_os_str().to_os_string();
+    temp.push(".blob");
+    let temp = PathBuf::from(temp);
+    fs::hard_link(&blob, &temp).with_context(|| format!("Failed to link {:?}", blob))?;
+    fs::rename(&temp, path).inspect_err(|_| {
+        fs::remove_file(&temp).ok();
+    })?;
+    Ok(())
+}
+
+/// Deletes blobs no backup entry links to any more, returning their paths
+/// and sizes.
+#[cfg(unix)]
+pub fn collect_garbage(blob_dir: &Path) -> Result<Vec<(PathBuf, u64)>> {
+    use std::os::unix::fs::MetadataExt;
+
+    let Ok(entries) = fs::read_dir(blob_dir) else {
+        return Ok(Vec::new());
+    };
+    let mut removed = Vec::new();
+    for entry in entries.filter_map(|e| e.ok()) {
+        let metadata = entry.metadata()?;
+        if metadata.is_file() && metadata.nlink() == 1 {
+            fs::remove_file(entry.path())?;
+            removed.push((entry.path(), metadata.len()));
+        }
+    }
+    Ok(removed)
+}
+
+#[cfg(not(unix))]
+pub fn collect_garbage(_blob_dir: &Path) -> Result<Vec<(PathBuf, u64)>> {
+    Ok(Vec::new())
+}
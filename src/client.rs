@@ -0,0 +1,427 @@
+use crate::models::{ConnectionDetails, DbType, Maintenance};
+use crate::split;
+use anyhow::{Context, Result};
+use flate2::read::GzDecoder;
+use std::io::Read;
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// A command for an external tool such as `mysqldump`. `DBR_<TOOL>_PATH`
+/// (e.g. `DBR_PG_DUMP_PATH`) overrides the binary, so tests and CI can point
+/// at a stub instead of a real database client.
+pub fn tool(name: &str) -> Command {
+    let var = format!("DBR_{}_PATH", name.to_uppercase().replace('-', "_"));
+    match std::env::var_os(var) {
+        Some(path) => Command::new(path),
+        None => Command::new(name),
+    }
+}
+
+/// Builds a `mysql`/`psql` client command for `conn`, connected to `database`.
+pub fn client_command(db_type: &DbType, conn: &ConnectionDetails, database: &str) -> Command {
+    match db_type {
+        DbType::MariaDB => {
+            let mut c = tool("mysql");
+            c.arg(format!("-h{}", conn.host))
+                .arg(format!("-P{}", conn.port))
+                .arg(format!("-u{}", conn.user));
+            set_password(&mut c, db_type, conn.password.as_deref());
+            if !database.is_empty() {
+                c.arg(database);
+            }
+            c
+        }
+        DbType::PostgreSQL => {
+            let mut c = tool("psql");
+            c.env("PGHOST", &conn.host)
+                .env("PGPORT", conn.port.to_string())
+                .env("PGUSER", &conn.user)
+                .env("PGDATABASE", database);
+            set_password(&mut c, db_type, conn.password.as_deref());
+            c.arg("-v").arg("ON_ERROR_STOP=1").arg("-q");
+            c
+        }
+        DbType::SQLite => {
+            let mut c = tool("sqlite3");
+            // Stop at the first failing statement, like ON_ERROR_STOP for psql
+            c.arg("-batch").arg("-bail").arg(database);
+            c
+        }
+    }
+}
+
+/// Hands `password` to a client or dump tool through `MYSQL_PWD`/`PGPASSWORD`.
+///
+/// `None` means no password was configured: the variable is left alone so
+/// the tool falls back to its own sources (`~/.my.cnf`, `~/.pgpass`).
+/// `Some("")` is an intentionally empty password and is passed as one.
+pub fn set_password(c: &mut Command, db_type: &DbType, password: Option<&str>) {
+    let Some(password) = password else {
+        return;
+    };
+    let var = match db_type {
+        DbType::MariaDB => "MYSQL_PWD",
+        DbType::PostgreSQL => "PGPASSWORD",
+        DbType::SQLite => return,
+    };
+    c.env(var, password);
+}
+
+/// Runs a single SQL statement and returns its unaligned, headerless output.
+pub fn run_sql(
+    db_type: &DbType,
+    conn: &ConnectionDetails,
+    database: &str,
+    sql: &str,
+) -> Result<String> {
+    let mut c = client_command(db_type, conn, database);
+    match db_type {
+        DbType::MariaDB => c.arg("-N").arg("-B").arg("-e").arg(sql),
+        DbType::PostgreSQL => c.arg("-At").arg("-c").arg(sql),
+        DbType::SQLite => c.arg("-noheader").arg("-separator").arg("\t").arg(sql),
+    };
+
+    let output = c
+        .output()
+        .with_context(|| format!("Failed to execute {} client", db_type))?;
+    if !output.status.success() {
+        let err_msg = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!("{} client failed: {}", db_type, err_msg.trim());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+/// Checks that the server accepts a connection to `database` and answers a query.
+/// For SQLite, that the database file exists; opening a missing one would create it.
+pub fn check_connection(db_type: &DbType, conn: &ConnectionDetails, database: &str) -> Result<()> {
+    if *db_type == DbType::SQLite && !Path::new(database).is_file() {
+        anyhow::bail!("SQLite database {} does not exist", database);
+    }
+    run_sql(db_type, conn, database, "SELECT 1").map(|_| ())
+}
+
+/// Runs `maintenance` over every table in `database`.
+pub fn run_maintenance(
+    db_type: &DbType,
+    conn: &ConnectionDetails,
+    database: &str,
+    maintenance: Maintenance,
+) -> Result<()> {
+    match db_type {
+        DbType::MariaDB => {
+            let tables = run_sql(
+                db_type,
+                conn,
+                database,
+                "SELECT table_name FROM information_schema.tables \
+                 WHERE table_schema = DATABASE() AND table_type = 'BASE TABLE'",
+            )?;
+            let tables: Vec<String> = tables
+                .lines()
+                .map(str::trim)
+                .filter(|t| !t.is_empty())
+                .map(|t| quote_ident(db_type, t))
+                .collect();
+            if tables.is_empty() {
+                return Ok(());
+            }
+            let statement = match maintenance {
+                Maintenance::Analyze => "ANALYZE TABLE",
+                Maintenance::Optimize => "OPTIMIZE TABLE",
+            };
+            // Per-table failures come back as result rows, not as an error:
+            // Table, Op, Msg_type, Msg_text
+            let output = run_sql(
+                db_type,
+                conn,
+                database,
+                &format!("{} {}", statement, tables.join(", ")),
+            )?;
+            let errors: Vec<String> = output
+                .lines()
+                .filter_map(|row| {
+                    let fields: Vec<&str> = row.split('\t').collect();
+                    match fields.as_slice() {
+                        [table, _, "error", message] => Some(format!("{}: {}", table, message)),
+                        _ => None,
+                    }
+                })
+                .collect();
+            if !errors.is_empty() {
+                anyhow::bail!("{} reported errors: {}", statement, errors.join("; "));
+            }
+            Ok(())
+        }
+        DbType::PostgreSQL | DbType::SQLite => {
+            let statement = match maintenance {
+                Maintenance::Analyze => "ANALYZE",
+                Maintenance::Optimize if *db_type == DbType::SQLite => "VACUUM; ANALYZE",
+                Maintenance::Optimize => "VACUUM ANALYZE",
+            };
+            run_sql(db_type, conn, database, statement).map(|_| ())
+        }
+    }
+}
+
+/// The server's configuration as `name<TAB>value` lines: `SHOW GLOBAL
+/// VARIABLES` on MariaDB, `pg_settings` on PostgreSQL.
+pub fn server_settings(db_type: &DbType, conn: &ConnectionDetails) -> Result<String> {
+    let output = match db_type {
+        DbType::MariaDB => run_sql(db_type, conn, "", "SHOW GLOBAL VARIABLES")?,
+        DbType::PostgreSQL => run_sql(
+            db_type,
+            conn,
+            maintenance_database(db_type),
+            "SELECT name || E'\\t' || setting || COALESCE(' ' || unit, '') \
+             FROM pg_settings ORDER BY name",
+        )?,
+        DbType::SQLite => anyhow::bail!("SQLite has no server configuration"),
+    };
+    Ok(output)
+}
+
+/// Lists the user databases on the server `conn` points at, skipping the
+/// server's own system databases unless `include_system` is set. Ones that
+/// can't be dumped at all (MariaDB's virtual schemas, `template0`) are
+/// always skipped.
+pub fn list_databases(
+    db_type: &DbType,
+    conn: &ConnectionDetails,
+    include_system: bool,
+) -> Result<Vec<String>> {
+    let sql = match (db_type, include_system) {
+        (DbType::SQLite, _) => anyhow::bail!("SQLite has no server to list databases on"),
+        (DbType::MariaDB, _) => "SHOW DATABASES",
+        (DbType::PostgreSQL, false) => {
+            "SELECT datname FROM pg_database WHERE NOT datistemplate ORDER BY datname"
+        }
+        (DbType::PostgreSQL, true) => {
+            "SELECT datname FROM pg_database WHERE datallowconn ORDER BY datname"
+        }
+    };
+    let (virtual_schemas, system): (&[&str], &[&str]) = match db_type {
+        DbType::MariaDB => (
+            &["information_schema", "performance_schema"],
+            &["mysql", "sys"],
+        ),
+        DbType::PostgreSQL | DbType::SQLite => (&[], &["postgres"]),
+    };
+
+    let output = run_sql(db_type, conn, maintenance_database(db_type), sql)?;
+    Ok(output
+        .lines()
+        .map(str::trim)
+        .filter(|name| !name.is_empty() && !virtual_schemas.contains(name))
+        .filter(|name| include_system || !system.contains(name))
+        .map(String::from)
+        .collect())
+}
+
+/// Whether `name` matches the glob `pattern`, where `*` matches any run of
+/// characters and `?` any single one.
+pub fn glob_match(pattern: &str, name: &str) -> bool {
+    fn matches(p: &[char], n: &[char]) -> bool {
+        match (p.first(), n.first()) {
+            (None, None) => true,
+            (Some('*'), _) => matches(&p[1..], n) || (!n.is_empty() && matches(p, &n[1..])),
+            (Some('?'), Some(_)) => matches(&p[1..], &n[1..]),
+            (Some(a), Some(b)) if a == b => matches(&p[1..], &n[1..]),
+            _ => false,
+        }
+    }
+    let p: Vec<char> = pattern.chars().collect();
+    let n: Vec<char> = name.chars().collect();
+    matches(&p, &n)
+}
+
+/// How a backup file is encoded, as identified by its leading bytes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BackupFormat {
+    Plain,
+    Gzip,
+    Zstd,
+    /// Encrypted with age, binary or ASCII-armored
+    Age,
+}
+
+impl BackupFormat {
+    pub fn detect(header: &[u8]) -> BackupFormat {
+        if header.starts_with(&[0x1f, 0x8b]) {
+            BackupFormat::Gzip
+        } else if header.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            BackupFormat::Zstd
+        } else if header.starts_with(b"age-encryption.org/")
+            || header.starts_with(b"-----BEGIN AGE ENCRYPTED FILE-----")
+        {
+            BackupFormat::Age
+        } else {
+            BackupFormat::Plain
+        }
+    }
+}
+
+/// Opens a backup for reading, transparently decompressing it.
+///
+/// The format is sniffed from the first bytes rather than trusted from the
+/// extension, so renamed files and pg_dump's native `-Z` output both work.
+/// Split backups are reassembled from their parts first.
+pub fn open_backup(path: &Path) -> Result<Box<dyn Read>> {
+    let mut raw = split::open_raw(path)?;
+    let mut header = Vec::with_capacity(64);
+    raw.by_ref().take(64).read_to_end(&mut header)?;
+    let format = BackupFormat::detect(&header);
+    let stream = std::io::Cursor::new(header).chain(raw);
+
+    match format {
+        BackupFormat::Plain => Ok(Box::new(stream)),
+        BackupFormat::Gzip => Ok(Box::new(GzDecoder::new(stream))),
+        BackupFormat::Zstd => anyhow::bail!(
+            "{:?} is zstd-compressed, which is not supported; decompress it with `zstd -d` first",
+            path
+        ),
+        BackupFormat::Age => anyhow::bail!(
+            "{:?} is encrypted with age and no key or passphrase is available to decrypt it",
+            path
+        ),
+    }
+}
+
+/// Pipes the dump at `path` into the client tool connected to `database`.
+pub fn restore_file(
+    db_type: &DbType,
+    conn: &ConnectionDetails,
+    database: &str,
+    path: &Path,
+) -> Result<()> {
+    if path.is_dir() {
+        return restore_directory(conn, database, path);
+    }
+    if *db_type == DbType::SQLite {
+        return restore_sqlite(database, path);
+    }
+    load_dump(db_type, conn, database, path)
+}
+
+/// Streams a SQL dump into the client tool connected to `database`.
+fn load_dump(
+    db_type: &DbType,
+    conn: &ConnectionDetails,
+    database: &str,
+    path: &Path,
+) -> Result<()> {
+    let mut reader = open_backup(path)?;
+
+    let mut c = client_command(db_type, conn, database);
+    c.stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped());
+
+    let mut child = c
+        .spawn()
+        .with_context(|| format!("Failed to execute {} client", db_type))?;
+    let mut stdin = child.stdin.take().context("Failed to open client stdin")?;
+    let copied = std::io::copy(&mut reader, &mut stdin);
+    drop(stdin);
+    let output = child.wait_with_output()?;
+
+    if !output.status.success() {
+        let err_msg = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!("Restore failed: {}", err_msg.trim());
+    }
+    copied.context("Failed to stream backup into client")?;
+    Ok(())
+}
+
+/// Replaces the SQLite database file at `database` with the backup at
+/// `path`: a file copy is put back as is, a `.dump` is loaded into a fresh
+/// file. Either way the new database is built beside the old one and moved
+/// over it only once complete.
+fn restore_sqlite(database: &str, path: &Path) -> Result<()> {
+    let target = Path::new(database);
+    let staged = sqlite_sidecar(target, ".restoring");
+    std::fs::remove_file(&staged).ok();
+
+    // Look past compression, so a gzipped copy of the file is still a copy
+    let mut header = Vec::new();
+    open_backup(path)?.take(16).read_to_end(&mut header)?;
+    if header.starts_with(SQLITE_HEADER) {
+        std::io::copy(
+            &mut open_backup(path)?,
+            &mut std::fs::File::create(&staged)?,
+        )
+        .with_context(|| format!("Failed to copy {:?} to {:?}", path, staged))?;
+    } else {
+        let staged_name = staged.to_string_lossy().to_string();
+        let loaded = load_dump(
+            &DbType::SQLite,
+            &ConnectionDetails::default(),
+            &staged_name,
+            path,
+        );
+        if let Err(e) = loaded {
+            std::fs::remove_file(&staged).ok();
+            return Err(e);
+        }
+    }
+
+    // A journal left by the old database would be replayed into the new one
+    for suffix in ["-wal", "-shm", "-journal"] {
+        std::fs::remove_file(sqlite_sidecar(target, suffix)).ok();
+    }
+    std::fs::rename(&staged, target)
+        .with_context(|| format!("Failed to move {:?} into place", staged))?;
+    Ok(())
+}
+
+/// A file SQLite keeps beside `database`, named by appending `suffix`
+/// (`-wal`, `-journal`, ...).
+pub fn sqlite_sidecar(database: &Path, suffix: &str) -> std::path::PathBuf {
+    let mut name = database.as_os_str().to_os_string();
+    name.push(suffix);
+    std::path::PathBuf::from(name)
+}
+
+/// The first bytes of every SQLite database file.
+pub const SQLITE_HEADER: &[u8] = b"SQLite format 3\0";
+
+/// Loads a mydumper directory backup with `myloader`.
+fn restore_directory(conn: &ConnectionDetails, database: &str, dir: &Path) -> Result<()> {
+    let mut c = tool("myloader");
+    c.arg("--host")
+        .arg(&conn.host)
+        .arg("--port")
+        .arg(conn.port.to_string())
+        .arg("--user")
+        .arg(&conn.user)
+        .arg("--directory")
+        .arg(dir)
+        .arg("--database")
+        .arg(database)
+        .arg("--overwrite-tables");
+    set_password(&mut c, &DbType::MariaDB, conn.password.as_deref());
+    let output = c
+        .stdout(Stdio::null())
+        .output()
+        .context("Failed to execute myloader")?;
+    if !output.status.success() {
+        let err_msg = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!("Restore failed: {}", err_msg.trim());
+    }
+    Ok(())
+}
+
+/// Quotes a database identifier for use in CREATE/DROP DATABASE.
+pub fn quote_ident(db_type: &DbType, name: &str) -> String {
+    match db_type {
+        DbType::MariaDB => format!("`{}`", name.replace('`', "``")),
+        DbType::PostgreSQL | DbType::SQLite => format!("\"{}\"", name.replace('"', "\"\"")),
+    }
+}
+
+/// Database to connect to when issuing server-level statements.
+pub fn maintenance_database(db_type: &DbType) -> &'static str {
+    match db_type {
+        DbType::MariaDB | DbType::SQLite => "",
+        DbType::PostgreSQL => "postgres",
+    }
+}
@@ -0,0 +1,172 @@
+use anyhow::Result;
+use sha2::{Digest, Sha256};
+use std::collections::BTreeMap;
+use std::io::{BufRead, BufReader, Read};
+
+/// The tables of a plain SQL dump, for telling what changed between two.
+#[derive(Debug, Default)]
+pub struct DumpSummary {
+    pub tables: BTreeMap<String, TableSummary>,
+    /// Hash of every statement that isn't a table's definition or rows
+    /// (views, routines, grants, ...)
+    pub other_hash: String,
+}
+
+#[derive(Debug, Default)]
+pub struct TableSummary {
+    /// The CREATE TABLE statement, without mysqldump's AUTO_INCREMENT counter
+    pub definition: Vec<String>,
+    /// Hash of the table's INSERT statements or COPY rows
+    pub data_hash: String,
+}
+
+#[derive(Default)]
+struct TableState {
+    definition: Vec<String>,
+    data: Sha256,
+}
+
+/// Reads a mysqldump or pg_dump plain-format dump, grouping its CREATE TABLE
+/// statements and rows by table.
+pub fn summarize(reader: impl Read) -> Result<DumpSummary> {
+    let mut tables: BTreeMap<String, TableState> = BTreeMap::new();
+    let mut other = Sha256::new();
+    // The table whose CREATE TABLE or COPY block is being read
+    let mut in_create: Option<String> = None;
+    let mut in_copy: Option<String> = None;
+
+    for line in BufReader::new(reader).split(b'\n') {
+        let line = String::from_utf8_lossy(&line?).into_owned();
+        if let Some(table) = &in_copy {
+            if line == "\\." {
+                in_copy = None;
+            } else {
+                let data = &mut tables.entry(table.clone()).or_default().data;
+                data.update(line.as_bytes());
+                data.update(b"\n");
+            }
+            continue;
+        }
+        if let Some(table) = &in_create {
+            let state = tables.entry(table.clone()).or_default();
+            state.definition.push(strip_auto_increment(&line));
+            if line.trim_end().ends_with(';') {
+                in_create = None;
+            }
+            continue;
+        }
+
+        if let Some(table) = line.strip_prefix("CREATE TABLE ").map(table_name) {
+            let state = tables.entry(table.clone()).or_default();
+            state.definition = vec![strip_auto_increment(&line)];
+            if !line.trim_end().ends_with(';') {
+                in_create = Some(table);
+            }
+        } else if let Some(table) = line.strip_prefix("INSERT INTO ").map(table_name) {
+            let data = &mut tables.entry(table).or_default().data;
+            data.update(line.as_bytes());
+            data.update(b"\n");
+        } else if let Some(table) = line.strip_prefix("COPY ").map(table_name) {
+            tables.entry(table.clone()).or_default();
+            in_copy = Some(table);
+        } else if !line.starts_with("--") {
+            other.update(line.as_bytes());
+            other.update(b"\n");
+        }
+    }
+
+    Ok(DumpSummary {
+        tables: tables
+            .into_iter()
+            .map(|(name, state)| {
+                let summary = TableSummary {
+                    definition: state.definition,
+                    data_hash: format!("{:x}", state.data.finalize()),
+                };
+                (name, summary)
+            })
+            .collect(),
+        other_hash: format!("{:x}", other.finalize()),
+    })
+}
+
+/// The table name at the start of `rest`, unquoted: "`t` (", "public.t (",
+/// "IF NOT EXISTS \"t\" (".
+fn table_name(rest: &str) -> String {
+    let rest = rest.strip_prefix("IF NOT EXISTS ").unwrap_or(rest);
+    rest.split(|c: char| c.is_whitespace() || c == '(')
+        .next()
+        .unwrap_or_default()
+        .replace(['`', '"'], "")
+}
+
+/// mysqldump writes the next AUTO_INCREMENT value into the table options,
+/// which changes with every insert rather than with the schema.
+fn strip_auto_increment(line: &str) -> String {
+    let Some(start) = line.find(" AUTO_INCREMENT=") else {
+        return line.to_string();
+    };
+    let value = &line[start + " AUTO_INCREMENT=".len()..];
+    let digits = value.chars().take_while(|c| c.is_ascii_digit()).count();
+    format!("{}{}", &line[..start], &value[digits..])
+}
+
+/// How the tables of two dumps differ.
+#[derive(Debug, Default)]
+pub struct Comparison {
+    pub added: Vec<String>,
+    pub removed: Vec<String>,
+    /// Tables whose definition changed, with the lines only in `a` (`-`) and
+    /// only in `b` (`+`)
+    pub schema_changed: Vec<(String, Vec<String>)>,
+    pub data_changed: Vec<String>,
+    pub other_changed: bool,
+}
+
+impl Comparison {
+    pub fn is_empty(&self) -> bool {
+        self.added.is_empty()
+            && self.removed.is_empty()
+            && self.schema_changed.is_empty()
+            && self.data_changed.is_empty()
+            && !self.other_changed
+    }
+}
+
+pub fn compare(a: &DumpSummary, b: &DumpSummary) -> Comparison {
+    let mut comparison = Comparison {
+        other_changed: a.other_hash != b.other_hash,
+        ..Comparison::default()
+    };
+    for (name, old) in &a.tables {
+        let Some(new) = b.tables.get(name) else {
+            comparison.removed.push(name.clone());
+            continue;
+        };
+        if old.definition != new.definition {
+            let mut lines: Vec<String> = old
+                .definition
+                .iter()
+                .filter(|l| !new.definition.contains(l))
+                .map(|l| format!("- {}", l.trim()))
+                .collect();
+            lines.extend(
+                new.definition
+                    .iter()
+                    .filter(|l| !old.definition.contains(l))
+                    .map(|l| format!("+ {}", l.trim())),
+            );
+            comparison.schema_changed.push((name.clone(), lines));
+        }
+        if old.data_hash != new.data_hash {
+            comparison.data_changed.push(name.clone());
+        }
+    }
+    comparison.added = b
+        .tables
+        .keys()
+        .filter(|name| !a.tables.contains_key(*name))
+        .cloned()
+        .collect();
+    comparison
+}
@@ -0,0 +1,101 @@
+use std::path::PathBuf;
+
+/// Why a backup failed, for callers that need to react to the kind of
+/// failure rather than just report it.
+#[derive(Debug, thiserror::Error)]
+pub enum BackupError {
+    #[error("{tool} was not found; is it installed and on PATH?")]
+    ToolMissing { tool: String },
+    #[error("Could not connect to {host}:{port}: {message}")]
+    ConnectionFailed {
+        host: String,
+        port: u16,
+        message: String,
+    },
+    #[error("Database '{database}' does not exist on {host}:{port}")]
+    DatabaseMissing {
+        database: String,
+        host: String,
+        port: u16,
+    },
+    #[error("{tool} failed: {message}")]
+    DumpFailed { tool: String, message: String },
+    #[error("{tool} was killed after running for {secs}s (timeout_secs)")]
+    TimedOut { tool: String, secs: u64 },
+    #[error("Upload to remote storage failed: {message}")]
+    RemoteFailed { message: String },
+    #[error("No space left on device while writing {path:?}")]
+    DiskFull { path: PathBuf },
+    #[error(transparent)]
+    Io(#[from] std::io::Error),
+    #[error(transparent)]
+    Other(#[from] anyhow::Error),
+}
+
+impl BackupError {
+    /// Maps an error from spawning `tool`, recognising a missing binary.
+    pub fn spawn(tool: &str, e: std::io::Error) -> BackupError {
+        if e.kind() == std::io::ErrorKind::NotFound {
+            BackupError::ToolMissing {
+                tool: tool.to_string(),
+            }
+        } else {
+            BackupError::Io(e)
+        }
+    }
+
+    /// Maps an error from writing `path`, recognising a full disk.
+    pub fn write(path: &std::path::Path, e: std::io::Error) -> BackupError {
+        if e.kind() == std::io::ErrorKind::StorageFull {
+            BackupError::DiskFull {
+                path: path.to_path_buf(),
+            }
+        } else {
+            BackupError::Io(e)
+        }
+    }
+
+    /// Classifies a failed dump tool run from its stderr.
+    pub fn dump_failed(
+        tool: &str,
+        host: &str,
+        port: u16,
+        database: &str,
+        stderr: &[u8],
+    ) -> BackupError {
+        let message = String::from_utf8_lossy(stderr).trim().to_string();
+        let lower = message.to_lowercase();
+        // Checked first: pg_dump reports this as a failed "connection to server"
+        let missing_markers = ["unknown database", "does not exist"];
+        if lower.contains("database") && missing_markers.iter().any(|m| lower.contains(m)) {
+            return BackupError::DatabaseMissing {
+                database: database.to_string(),
+                host: host.to_string(),
+                port,
+            };
+        }
+        let connection_markers = [
+            "can't connect",
+            "access denied",
+            "unknown mysql server host",
+            "unknown server host",
+            "connection refused",
+            "could not connect",
+            "could not translate host name",
+            "connection to server",
+            "password authentication failed",
+        ];
+        if connection_markers.iter().any(|m| lower.contains(m)) {
+            BackupError::ConnectionFailed {
+                host: host.to_string(),
+                port,
+                message,
+            }
+        } else {
+            BackupError::DumpFailed {
+                tool: tool.to_string(),
+                message,
+            }
+        }
+    }
+}
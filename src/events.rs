@@ -0,0 +1,70 @@
+use chrono::{DateTime, Local};
+use serde::Serialize;
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+/// Lifecycle events printed to stdout, one JSON object per line, under
+/// `daemon --events json`.
+#[derive(Debug, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum Event<'a> {
+    BackupStarted {
+        name: &'a str,
+    },
+    BackupSucceeded {
+        name: &'a str,
+        size_bytes: u64,
+        duration_secs: f64,
+    },
+    /// The run finished without writing a new backup (deduplicated or debounced)
+    BackupSkipped {
+        name: &'a str,
+        reason: &'a str,
+    },
+    BackupFailed {
+        name: &'a str,
+        error: String,
+    },
+    /// A new backup's size is far from the average of the previous ones
+    SizeAnomaly {
+        name: &'a str,
+        size_bytes: u64,
+        average_bytes: u64,
+    },
+    RotationPerformed {
+        name: &'a str,
+        removed: Vec<String>,
+        bytes_freed: u64,
+    },
+    ConfigReloaded {
+        databases: usize,
+    },
+}
+
+#[derive(Serialize)]
+struct Line<'a> {
+    timestamp: DateTime<Local>,
+    #[serde(flatten)]
+    event: &'a Event<'a>,
+}
+
+pub fn enable() {
+    ENABLED.store(true, Ordering::Relaxed);
+}
+
+pub fn emit(event: Event) {
+    if !ENABLED.load(Ordering::Relaxed) {
+        return;
+    }
+    let line = Line {
+        timestamp: Local::now(),
+        event: &event,
+    };
+    if let Ok(json) = serde_json::to_string(&line) {
+        let mut stdout = std::io::stdout().lock();
+        writeln!(stdout, "{}", json).ok();
+        stdout.flush().ok();
+    }
+}
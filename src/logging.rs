@@ -0,0 +1,207 @@
+use log::{LevelFilter, Log, Metadata, Record};
+use simplelog::{Config, SharedLogger, WriteLogger};
+use std::collections::HashMap;
+use std::fs;
+use std::future::Future;
+use std::io::{self, Write};
+use std::path::{Path, PathBuf};
+use std::sync::Mutex;
+
+tokio::task_local! {
+    /// Name of the config whose backup the current task is running
+    static DATABASE: String;
+}
+
+/// Runs `f` with its log records attributed to the config `name`.
+pub async fn scope<F: Future>(name: &str, f: F) -> F::Output {
+    DATABASE.scope(name.to_string(), f).await
+}
+
+fn current() -> Option<String> {
+    DATABASE.try_with(|name| name.clone()).ok()
+}
+
+/// Per-config log file, `<dir>/<name>.log`.
+pub fn database_log_path(dir: &Path, name: &str) -> PathBuf {
+    dir.join(format!("{}.log", crate::file_stem(name)))
+}
+
+/// An append-mode log file that rolls over to `<path>.1` once it reaches
+/// `max_bytes`, shifting older files up to `<path>.<keep>`. Rolling waits
+/// for the end of a line so records aren't split between files.
+pub struct RotatingFile {
+    path: PathBuf,
+    file: fs::File,
+    written: u64,
+    at_line_start: bool,
+    max_bytes: u64,
+    keep: usize,
+}
+
+impl RotatingFile {
+    pub fn open(path: PathBuf, max_bytes: u64, keep: usize) -> io::Result<RotatingFile> {
+        let file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)?;
+        let written = file.metadata()?.len();
+        Ok(RotatingFile {
+            path,
+            file,
+            written,
+            at_line_start: true,
+            max_bytes,
+            keep,
+        })
+    }
+
+    fn rolled(&self, n: usize) -> PathBuf {
+        let mut name = self.path.as_os_str().to_os_string();
+        name.push(format!(".{}", n));
+        PathBuf::from(name)
+    }
+
+    fn roll(&mut self) -> io::Result<()> {
+        if self.keep == 0 {
+            fs::remove_file(&self.path).ok();
+        } else {
+            for n in (1..self.keep).rev() {
+                fs::rename(self.rolled(n), self.rolled(n + 1)).ok();
+            }
+            fs::rename(&self.path, self.rolled(1))?;
+        }
+        self.file = fs::OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        self.written = 0;
+        Ok(())
+    }
+}
+
+impl Write for RotatingFile {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.max_bytes > 0 && self.at_line_start && self.written >= self.max_bytes {
+            self.roll()?;
+        }
+        let n = self.file.write(buf)?;
+        self.written += n as u64;
+        if n > 0 {
+            self.at_line_start = buf[n - 1] == b'\n';
+        }
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.file.flush()
+    }
+}
+
+/// Passes on only records logged outside any config's scope, for a combined
+/// log that leaves per-database messages to their own files.
+pub struct Unscoped(pub Box<dyn SharedLogger>);
+
+impl Log for Unscoped {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        self.0.enabled(metadata)
+    }
+
+    fn log(&self, record: &Record) {
+        if current().is_none() {
+            self.0.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        self.0.flush()
+    }
+}
+
+impl SharedLogger for Unscoped {
+    fn level(&self) -> LevelFilter {
+        self.0.level()
+    }
+
+    fn config(&self) -> Option<&Config> {
+        self.0.config()
+    }
+
+    fn as_log(self: Box<Self>) -> Box<dyn Log> {
+        Box::new(*self)
+    }
+}
+
+/// Appends each config's records to its own file in `dir`, opened on first use.
+pub struct DatabaseLogs {
+    dir: PathBuf,
+    level: LevelFilter,
+    max_bytes: u64,
+    keep: usize,
+    files: Mutex<HashMap<String, Box<WriteLogger<RotatingFile>>>>,
+}
+
+impl DatabaseLogs {
+    pub fn new(dir: PathBuf, level: LevelFilter, max_bytes: u64, keep: usize) -> Box<DatabaseLogs> {
+        Box::new(DatabaseLogs {
+            dir,
+            level,
+            max_bytes,
+            keep,
+            files: Mutex::new(HashMap::new()),
+        })
+    }
+}
+
+impl Log for DatabaseLogs {
+    fn enabled(&self, metadata: &Metadata) -> bool {
+        metadata.level() <= self.level
+    }
+
+    fn log(&self, record: &Record) {
+        let Some(name) = current() else {
+            return;
+        };
+        let Ok(mut files) = self.files.lock() else {
+            return;
+        };
+        if !files.contains_key(&name) {
+            let path = database_log_path(&self.dir, &name);
+            let file = fs::create_dir_all(&self.dir)
+                .and_then(|_| RotatingFile::open(path, self.max_bytes, self.keep));
+            match file {
+                Ok(file) => {
+                    files.insert(
+                        name.clone(),
+                        WriteLogger::new(self.level, Config::default(), file),
+                    );
+                }
+                Err(_) => return,
+            }
+        }
+        if let Some(logger) = files.get(&name) {
+            logger.log(record);
+        }
+    }
+
+    fn flush(&self) {
+        if let Ok(files) = self.files.lock() {
+            for logger in files.values() {
+                logger.flush();
+            }
+        }
+    }
+}
+
+impl SharedLogger for DatabaseLogs {
+    fn level(&self) -> LevelFilter {
+        self.level
+    }
+
+    fn config(&self) -> Option<&Config> {
+        None
+    }
+
+    fn as_log(self: Box<Self>) -> Box<dyn Log> {
+        Box::new(*self)
+    }
+}
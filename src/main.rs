@@ -1,119 +1,758 @@
+mod blobs;
+mod client;
+mod compare;
+mod error;
+mod events;
+mod logging;
+mod manifest;
 mod models;
+mod notify;
+mod progress;
+mod remote;
+mod secrets;
+mod split;
+mod state;
+mod throttle;
+mod vault;
 
 use anyhow::{Context, Result};
 use chrono::Local;
-use clap::{Parser, Subcommand};
+use clap::{Args, CommandFactory, Parser, Subcommand, ValueEnum};
 
 use comfy_table::{Cell, Color, Table};
 use cron::Schedule;
-use dialoguer::{theme::ColorfulTheme, Confirm, Input, Password, Select};
+use dialoguer::{theme::ColorfulTheme, Confirm, Input, MultiSelect, Password, Select};
 use directories::ProjectDirs;
-use log::{error, info, warn};
-use models::{AppConfig, ConnectionDetails, DatabaseConfig, DbType};
-use simplelog::{CombinedLogger, Config, LevelFilter, TermLogger, TerminalMode, WriteLogger};
+use error::BackupError;
+use events::Event;
+use flate2::write::GzEncoder;
+use log::{debug, error, info, warn};
+use manifest::{BinlogPosition, Manifest, RestoreCheck, SourceFile};
+use models::{
+    AppConfig, BlackoutPolicy, Compression, ConnectionDetails, DatabaseConfig, DbType,
+    DedupStorage, DumpTool, FilenameCollision, LogFiles, OverlapPolicy, RemoteConfig,
+    RetentionPolicy, StorageLayout,
+};
+use simplelog::{
+    CombinedLogger, Config, LevelFilter, SharedLogger, TermLogger, TerminalMode, WriteLogger,
+};
+use state::{AppState, DaemonStatus};
 use std::fs;
+use std::io::{IsTerminal, Read, Write};
 use std::path::PathBuf;
 use std::process::Command;
 use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, AtomicU64, AtomicUsize, Ordering};
+use std::sync::{Arc, OnceLock};
 use std::time::Duration;
 use tokio::time::sleep;
 
 #[derive(Parser)]
 #[command(author, version, about, long_about = None)]
 struct Cli {
+    /// Path to the config file (format is detected from the extension)
+    #[arg(long, global = true)]
+    config: Option<PathBuf>,
+    /// Override the config file format instead of detecting it
+    #[arg(long, global = true, value_enum)]
+    config_format: Option<ConfigFormat>,
+    /// When to use colored output (defaults to never if NO_COLOR is set)
+    #[arg(long, global = true, value_enum)]
+    color: Option<ColorMode>,
+    /// Log successful backups at debug level so the log only shows problems
+    #[arg(long, global = true)]
+    quiet_success: bool,
+    /// Skip the confirmation for `delete <name>`; restores still ask
+    #[arg(long, global = true)]
+    confirm_destructive_only: bool,
+    /// Run at most this many dumps against one host:port at a time, across
+    /// every dbr process on this machine; the rest wait their turn
+    #[arg(long, global = true, env = "DBR_CONCURRENCY_PER_HOST")]
+    concurrency_per_host: Option<std::num::NonZeroUsize>,
+    /// Async runtime worker threads (defaults to one per CPU core)
+    #[arg(long, global = true, env = "DBR_WORKER_THREADS")]
+    worker_threads: Option<std::num::NonZeroUsize>,
+    /// Upper limit on threads for blocking work such as file I/O (tokio default: 512)
+    #[arg(long, global = true, env = "DBR_BLOCKING_THREADS")]
+    blocking_threads: Option<std::num::NonZeroUsize>,
+    /// Only parse and validate the config, creating no directories or log files
+    #[arg(long)]
+    config_check: bool,
     #[command(subcommand)]
-    command: Commands,
+    command: Option<Commands>,
+}
+
+#[derive(Args)]
+struct RunArgs {
+    /// Databases to back up (name or ID). Prompts on a terminal if omitted.
+    names: Vec<String>,
+    /// Back up every configured database without prompting
+    #[arg(long)]
+    all: bool,
+    /// Back up only the databases whose latest `run` failed
+    #[arg(long, conflicts_with_all = ["names", "all", "stdout"])]
+    retry_failed: bool,
+    /// Also back up databases disabled with `stop`
+    #[arg(long)]
+    include_disabled: bool,
+    /// Write backups here instead of each config's output directory (config is not changed)
+    #[arg(long)]
+    output_dir: Option<PathBuf>,
+    /// Skip rotating old backups after this run
+    #[arg(long)]
+    no_rotate: bool,
+    /// Tag these backups as manual so they follow `manual_retention_count`
+    #[arg(long)]
+    manual: bool,
+    /// Keep the backup even if identical to the last one, ignore min_interval_seconds,
+    /// and back up into an output directory full of other files
+    #[arg(long)]
+    force: bool,
+    /// Extra arguments for mysqldump/pg_dump on this run, quoted as in a shell
+    #[arg(long, allow_hyphen_values = true)]
+    dump_args: Option<String>,
+    /// Stream a single database's dump to stdout (no file, dedup or rotation)
+    #[arg(long)]
+    stdout: bool,
+    /// Read the dump's output at most this many bytes per second (overrides read_rate_limit)
+    #[arg(long, value_parser = clap::value_parser!(u64).range(1..))]
+    limit_rate: Option<u64>,
+    /// Add a label to the file name after the timestamp, e.g. `before-v2-migration`
+    #[arg(long, value_parser = parse_label, conflicts_with = "stdout")]
+    label: Option<String>,
+    /// How many databases to back up at once
+    #[arg(long, short, default_value_t = 4, value_parser = clap::value_parser!(u16).range(1..))]
+    jobs: u16,
+}
+
+/// Labels become part of file names, so only allow characters that are safe
+/// there and can't be mistaken for the label separator or an extension.
+fn parse_label(label: &str) -> Result<String, String> {
+    if label.is_empty()
+        || label.contains(LABEL_SEPARATOR)
+        || !label
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+        return Err(format!(
+            "'{}' is not a valid label; use letters, digits, '-' and '_' (no \"{}\")",
+            label, LABEL_SEPARATOR
+        ));
+    }
+    Ok(label.to_string())
+}
+
+#[derive(Args)]
+struct RestoreArgs {
+    /// Database to restore (name or ID); asks when left out
+    name: Option<String>,
+    /// Backup to restore; asks, listing the database's backups, when left out
+    file: Option<PathBuf>,
+    /// Restore the most recent backup of this config
+    #[arg(long, conflicts_with = "file")]
+    latest: bool,
+    /// Restore to this host instead of the config's own
+    #[arg(long)]
+    target_host: Option<String>,
+    /// Restore into this database instead of the config's own
+    #[arg(long)]
+    target_database: Option<String>,
+    /// Connect as this user (prompts for its password)
+    #[arg(long)]
+    target_user: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+enum ConfigFormat {
+    Toml,
+    Yaml,
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+enum EventFormat {
+    Json,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+enum ColorMode {
+    Auto,
+    Always,
+    Never,
+}
+
+static CONFIG_PATH: OnceLock<PathBuf> = OnceLock::new();
+static CONFIG_FORMAT: OnceLock<ConfigFormat> = OnceLock::new();
+static COLOR_MODE: OnceLock<ColorMode> = OnceLock::new();
+static QUIET_SUCCESS: AtomicBool = AtomicBool::new(false);
+static CONFIRM_DESTRUCTIVE_ONLY: AtomicBool = AtomicBool::new(false);
+/// `--concurrency-per-host`, or 0 for no limit
+static CONCURRENCY_PER_HOST: AtomicUsize = AtomicUsize::new(0);
+/// Log level from RUST_LOG, used when the config doesn't set `log_level`
+static ENV_LOG_LEVEL: OnceLock<LevelFilter> = OnceLock::new();
+
+/// Logs routine success messages at info, or debug under `--quiet-success`.
+macro_rules! success {
+    ($($arg:tt)*) => {
+        if QUIET_SUCCESS.load(Ordering::Relaxed) {
+            log::debug!($($arg)*)
+        } else {
+            log::info!($($arg)*)
+        }
+    };
+}
+
+#[derive(Args)]
+struct DiscoverArgs {
+    /// Server type
+    #[arg(long = "type", value_enum)]
+    db_type: DbType,
+    #[arg(long, default_value = "localhost")]
+    host: String,
+    /// Defaults to the standard port for the server type
+    #[arg(long)]
+    port: Option<u16>,
+    /// Account able to list every database on the server
+    #[arg(long)]
+    user: String,
+    /// Read the password from the first line of stdin instead of prompting
+    #[arg(long)]
+    password_stdin: bool,
 }
 
 #[derive(Subcommand)]
 enum Commands {
     /// Add a new database configuration
-    Add,
+    Add {
+        /// Read the password from the first line of stdin instead of prompting
+        #[arg(long)]
+        password_stdin: bool,
+    },
+    /// Guided setup that adds several databases from one server at once
+    Init {
+        /// Read the password from the first line of stdin instead of prompting
+        #[arg(long)]
+        password_stdin: bool,
+    },
+    /// List the databases on a server and add the selected ones
+    Discover(DiscoverArgs),
     /// List all database configurations
-    List,
+    List {
+        /// Show next-run times in UTC instead of local time
+        #[arg(long)]
+        utc: bool,
+        /// Also show each config's description, and check the last backup's
+        /// SHA-256 checksum (which reads the whole backup)
+        #[arg(long, short)]
+        verbose: bool,
+    },
     /// Edit an existing database configuration
     Edit { name: Option<String> },
     /// Delete a database configuration
     Delete { name: Option<String> },
     /// Run backups immediately for all configured databases
-    Run,
+    Run(RunArgs),
+    /// Back up one database right now, with progress and a summary
+    Now {
+        /// Database to back up (name or ID). Prompts if omitted.
+        name: Option<String>,
+    },
+    /// Check that databases accept a connection and answer a query
+    Test {
+        /// Database to check (name or ID). Checks all if omitted.
+        name: Option<String>,
+    },
     /// Run in daemon mode (continuous background backups based on schedule)
-    Daemon,
+    Daemon {
+        /// Show a live status table instead of terminal log output
+        #[arg(long)]
+        watch: bool,
+        /// Print lifecycle events to stdout for a supervisor; logs go to stderr
+        #[arg(long, value_enum, conflicts_with = "watch")]
+        events: Option<EventFormat>,
+        /// Run whatever is due since the last invocation, then exit (for driving from cron)
+        #[arg(long, conflicts_with = "watch")]
+        oneshot: bool,
+        /// On startup, run once each backup whose last fire time passed while
+        /// the daemon was down
+        #[arg(long, conflicts_with = "oneshot")]
+        catch_up: bool,
+    },
     /// Enable a database configuration
     Start { name: String },
     /// Disable a database configuration
     Stop { name: String },
+    /// Set or clear the backup schedule of a database configuration
+    Schedule {
+        name: String,
+        /// Remove the schedule so the config only runs manually
+        #[arg(long)]
+        clear: bool,
+    },
+    /// Change or remove the password of a database configuration
+    SetPassword {
+        name: String,
+        /// Read the new password from the first line of stdin instead of prompting
+        #[arg(long, conflicts_with_all = ["clear", "empty"])]
+        password_stdin: bool,
+        /// Remove the stored password, leaving the client's own defaults (~/.my.cnf, ~/.pgpass)
+        #[arg(long)]
+        clear: bool,
+        /// Store an intentionally empty password, distinct from having none
+        #[arg(long, conflicts_with = "clear")]
+        empty: bool,
+    },
+    /// Re-encrypt the stored passwords under a new passphrase
+    RotateKey,
+    /// Show whether the daemon is running, its PID and session activity
+    DaemonStatus,
+    /// Report each database's last backup and whether it has missed its schedule
+    Status {
+        /// Print a JSON array instead of a table
+        #[arg(long)]
+        json: bool,
+    },
+    /// Show cumulative backup statistics
+    Stats { name: Option<String> },
+    /// Restore a backup file into a database
+    Restore(RestoreArgs),
+    /// Show the manifest of a backup file and check the file against it
+    Inspect { file: PathBuf },
+    /// Compare two backups and summarize which tables were added, removed or changed
+    Compare { a: PathBuf, b: PathBuf },
+    /// Check stored backups against their manifests' sizes and content hashes
+    Verify {
+        /// Databases whose backups to check (name or ID)
+        #[arg(required_unless_present = "all")]
+        names: Vec<String>,
+        /// Check every configured database's backups
+        #[arg(long, conflicts_with = "names")]
+        all: bool,
+    },
+    /// Check the configuration and backup directories for problems
+    Doctor {
+        /// Repair what can be repaired safely, asking before each change
+        #[arg(long)]
+        fix: bool,
+        /// Apply fixes without asking
+        #[arg(long, requires = "fix")]
+        yes: bool,
+    },
+    /// Remove saved state (statistics, run times) for configs that no longer exist
+    Gc,
+    /// Print the end of the backup log
+    Logs {
+        /// Show this database's own log (needs `log_files` set to Both or PerDatabase)
+        #[arg(long)]
+        name: Option<String>,
+        /// Number of lines to show
+        #[arg(short = 'n', long, default_value_t = 50)]
+        lines: usize,
+        /// Keep printing new lines as they are written
+        #[arg(short, long)]
+        follow: bool,
+    },
 }
 
-#[tokio::main]
-async fn main() -> Result<()> {
+fn main() -> Result<()> {
     if std::env::var("RUST_LOG").is_err() {
         std::env::set_var("RUST_LOG", "info");
     }
 
+    let cli = Cli::parse();
+
+    let mut runtime = tokio::runtime::Builder::new_multi_thread();
+    runtime.enable_all();
+    if let Some(n) = cli.worker_threads {
+        runtime.worker_threads(n.get());
+    }
+    if let Some(n) = cli.blocking_threads {
+        runtime.max_blocking_threads(n.get());
+    }
+    runtime
+        .build()
+        .context("Failed to start async runtime")?
+        .block_on(run(cli))
+}
+
+async fn run(cli: Cli) -> Result<()> {
+    if let Some(path) = cli.config {
+        CONFIG_PATH.set(path).ok();
+    }
+    if let Some(format) = cli.config_format {
+        CONFIG_FORMAT.set(format).ok();
+    }
+    let color = cli.color.unwrap_or_else(|| {
+        // https://no-color.org: any non-empty value disables color
+        match std::env::var("NO_COLOR") {
+            Ok(v) if !v.is_empty() => ColorMode::Never,
+            _ => ColorMode::Auto,
+        }
+    });
+    COLOR_MODE.set(color).ok();
+    QUIET_SUCCESS.store(cli.quiet_success, Ordering::Relaxed);
+    CONFIRM_DESTRUCTIVE_ONLY.store(cli.confirm_destructive_only, Ordering::Relaxed);
+    if let Some(n) = cli.concurrency_per_host {
+        CONCURRENCY_PER_HOST.store(n.get(), Ordering::Relaxed);
+    }
+
+    if cli.config_check {
+        return command_config_check();
+    }
+    let Some(command) = cli.command else {
+        Cli::command()
+            .error(
+                clap::error::ErrorKind::MissingSubcommand,
+                "a subcommand is required unless --config-check is given",
+            )
+            .exit();
+    };
+
     // Initialize logging
     let config_dir = ProjectDirs::from("com", "db-shield", "db-backup-rs")
         .map(|dirs| dirs.config_dir().to_path_buf())
         .ok_or_else(|| anyhow::anyhow!("Could not determine config directory"))?;
 
-    if !config_dir.exists() {
+    // Commands that only read state don't need a log file; the rest append to it
+    let writes_log = !matches!(
+        command,
+        Commands::List { .. }
+            | Commands::DaemonStatus
+            | Commands::Stats { .. }
+            | Commands::Inspect { .. }
+            | Commands::Compare { .. }
+            | Commands::Verify { .. }
+            | Commands::Logs { .. }
+            | Commands::Test { .. }
+            | Commands::Status { .. }
+    );
+    if writes_log && !config_dir.exists() {
         fs::create_dir_all(&config_dir)?;
     }
 
-    let log_file = fs::File::create(config_dir.join("backup.log"))?;
+    let env_level = std::env::var("RUST_LOG")
+        .ok()
+        .and_then(|v| v.parse::<LevelFilter>().ok())
+        .unwrap_or(LevelFilter::Info);
+    ENV_LOG_LEVEL.set(env_level).ok();
 
-    CombinedLogger::init(vec![
-        TermLogger::new(
-            LevelFilter::Info,
+    // Read leniently, and without migrating: the command itself loads the config
+    let log_config = locate_config_path()
+        .ok()
+        .filter(|path| path.exists())
+        .and_then(|path| read_config(&path).ok())
+        .map(|(config, _)| config)
+        .unwrap_or_default();
+    let max_level = configured_log_level(&log_config).unwrap_or(env_level);
+    // Loggers pass everything and `log::set_max_level` does the filtering, so
+    // the daemon can change it on SIGHUP
+    let level = LevelFilter::Trace;
+    let (max_bytes, keep) = (log_config.log_max_bytes, log_config.log_keep);
+    let mut loggers: Vec<Box<dyn SharedLogger>> = Vec::new();
+    if writes_log {
+        let log_file = logging::RotatingFile::open(config_dir.join("backup.log"), max_bytes, keep)?;
+        let combined = WriteLogger::new(level, Config::default(), log_file);
+        loggers = match log_config.log_files {
+            LogFiles::Combined => vec![combined],
+            LogFiles::Both => vec![
+                combined,
+                logging::DatabaseLogs::new(config_dir.join("logs"), level, max_bytes, keep),
+            ],
+            LogFiles::PerDatabase => vec![
+                Box::new(logging::Unscoped(combined)),
+                logging::DatabaseLogs::new(config_dir.join("logs"), level, max_bytes, keep),
+            ],
+        };
+    }
+    // The watch dashboard redraws the terminal, so only log to the file there
+    if !matches!(command, Commands::Daemon { watch: true, .. }) {
+        // Keep stdout clean for the dump itself when streaming, or for events
+        let terminal_mode = match &command {
+            Commands::Run(args) if args.stdout => TerminalMode::Stderr,
+            Commands::Daemon {
+                events: Some(_), ..
+            } => TerminalMode::Stderr,
+            _ => TerminalMode::Mixed,
+        };
+        loggers.push(TermLogger::new(
+            level,
             Config::default(),
-            TerminalMode::Mixed,
-            simplelog::ColorChoice::Auto,
-        ),
-        WriteLogger::new(LevelFilter::Info, Config::default(), log_file),
-    ])
-    .unwrap_or_else(|e| println!("Failed to init logger: {}", e));
-
-    let cli = Cli::parse();
+            terminal_mode,
+            match color {
+                ColorMode::Auto => simplelog::ColorChoice::Auto,
+                ColorMode::Always => simplelog::ColorChoice::Always,
+                ColorMode::Never => simplelog::ColorChoice::Never,
+            },
+        ));
+    }
+    CombinedLogger::init(loggers).unwrap_or_else(|e| println!("Failed to init logger: {}", e));
+    log::set_max_level(max_level);
 
-    match cli.command {
-        Commands::Add => command_add().await?,
-        Commands::List => command_list()?,
+    match command {
+        Commands::Add { password_stdin } => command_add(password_stdin).await?,
+        Commands::Init { password_stdin } => command_init(password_stdin).await?,
+        Commands::Discover(args) => command_discover(args).await?,
+        Commands::List { utc, verbose } => command_list(utc, verbose)?,
         Commands::Edit { name } => command_edit(name).await?,
         Commands::Delete { name } => command_delete(name).await?,
-        Commands::Run => command_run().await?,
-        Commands::Daemon => command_daemon().await?,
+        Commands::Run(args) => command_run(args).await?,
+        Commands::Now { name } => command_now(name).await?,
+        Commands::Test { name } => command_test(name)?,
+        Commands::Daemon {
+            watch,
+            events,
+            oneshot,
+            catch_up,
+        } => {
+            if events.is_some() {
+                events::enable();
+            }
+            if oneshot {
+                command_daemon_oneshot().await?
+            } else {
+                command_daemon(watch, catch_up).await?
+            }
+        }
         Commands::Start { name } => command_start(name).await?,
+        Commands::SetPassword {
+            name,
+            password_stdin,
+            clear,
+            empty,
+        } => command_set_password(name, password_stdin, clear, empty).await?,
+        Commands::RotateKey => command_rotate_key()?,
         Commands::Stop { name } => command_stop(name).await?,
+        Commands::Schedule { name, clear } => command_schedule(name, clear).await?,
+        Commands::DaemonStatus => command_daemon_status()?,
+        Commands::Status { json } => command_status(json)?,
+        Commands::Stats { name } => command_stats(name)?,
+        Commands::Restore(args) => command_restore(args).await?,
+        Commands::Inspect { file } => command_inspect(file)?,
+        Commands::Verify { names, all } => command_verify(names, all)?,
+        Commands::Gc => command_gc()?,
+        Commands::Compare { a, b } => command_compare(a, b)?,
+        Commands::Doctor { fix, yes } => command_doctor(fix, yes)?,
+        Commands::Logs {
+            name,
+            lines,
+            follow,
+        } => command_logs(name, lines, follow).await?,
     }
 
     Ok(())
 }
 
 fn get_config_path() -> Result<PathBuf> {
+    let path = locate_config_path()?;
+    if CONFIG_PATH.get().is_none() {
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+    }
+    Ok(path)
+}
+
+/// Like `get_config_path`, but without creating the config directory.
+fn locate_config_path() -> Result<PathBuf> {
+    if let Some(path) = CONFIG_PATH.get() {
+        return Ok(path.clone());
+    }
+
     let proj_dirs = ProjectDirs::from("com", "db-shield", "db-backup-rs")
         .context("Could not determine config directory")?;
     let config_dir = proj_dirs.config_dir();
-    fs::create_dir_all(config_dir)?;
+
+    // Use whichever config file already exists, falling back to TOML for new setups
+    for ext in ["toml", "yaml", "yml", "json"] {
+        let path = config_dir.join(format!("config.{}", ext));
+        if path.exists() {
+            return Ok(path);
+        }
+    }
     Ok(config_dir.join("config.toml"))
 }
 
+fn config_format(path: &std::path::Path) -> ConfigFormat {
+    if let Some(format) = CONFIG_FORMAT.get() {
+        return *format;
+    }
+    match path.extension().and_then(|e| e.to_str()) {
+        Some("yaml") | Some("yml") => ConfigFormat::Yaml,
+        Some("json") => ConfigFormat::Json,
+        _ => ConfigFormat::Toml,
+    }
+}
+
 fn load_config() -> Result<AppConfig> {
-    let config_path = get_config_path()?;
+    let config_path = locate_config_path()?;
     if !config_path.exists() {
         return Ok(AppConfig::default());
     }
-    let content = fs::read_to_string(&config_path)?;
-    let config: AppConfig = toml::from_str(&content)?;
+    let (config, migrations) = read_config(&config_path)?;
+    secrets::set_encryption(&config);
+    for db in &config.databases {
+        db.validate()
+            .with_context(|| format!("Invalid configuration for '{}'", db.name))?;
+    }
+
+    // Migrated in memory only: reading the config never rewrites it. The
+    // daemon reloads it every tick, so say so once per process.
+    static LOGGED: AtomicBool = AtomicBool::new(false);
+    if !migrations.is_empty() && !LOGGED.swap(true, Ordering::Relaxed) {
+        for step in &migrations {
+            info!("Migrated config {:?} in memory: {}", config_path, step);
+        }
+        info!("The config is saved migrated by the next command that changes it, or `dbr doctor --fix`");
+    }
     Ok(config)
 }
 
+/// Parses the config file and migrates it in memory, without validating or
+/// saving it. Returns the migration steps that were applied.
+fn read_config(config_path: &std::path::Path) -> Result<(AppConfig, Vec<String>)> {
+    let content = fs::read_to_string(config_path)?;
+    // Parse into a format-neutral document first so older schemas can be migrated
+    let mut doc: serde_json::Value = match config_format(config_path) {
+        ConfigFormat::Toml => toml::from_str(&content)?,
+        ConfigFormat::Yaml => serde_yaml::from_str(&content)?,
+        ConfigFormat::Json => serde_json::from_str(&content)?,
+    };
+    let migrations = models::migrate_config(&mut doc)?;
+    let mut config: AppConfig = serde_json::from_value(doc)?;
+    for db in &mut config.databases {
+        if let Some(schedule) = &mut db.schedule {
+            *schedule = models::normalize_schedule(schedule);
+        }
+    }
+    Ok((config, migrations))
+}
+
+/// Saves the config from a command that stores passwords, setting up
+/// their encryption if it isn't yet.
 fn save_config(config: &AppConfig) -> Result<()> {
+    write_config(config, true)
+}
+
+/// Saves the config from a command that changes something else. It never
+/// asks for a passphrase: if encryption isn't set up yet and there is no
+/// `DB_BACKUP_KEY`, plaintext passwords are kept as they are.
+fn save_config_unprompted(config: &AppConfig) -> Result<()> {
+    write_config(config, false)
+}
+
+fn write_config(config: &AppConfig, setup_encryption: bool) -> Result<()> {
+    let config_path = get_config_path()?;
+    let mut config = config.clone();
+    secrets::encrypt_passwords(&mut config, setup_encryption)?;
+    let content = match config_format(&config_path) {
+        ConfigFormat::Toml => toml::to_string_pretty(&config)?,
+        ConfigFormat::Yaml => serde_yaml::to_string(&config)?,
+        ConfigFormat::Json => serde_json::to_string_pretty(&config)?,
+    };
+    // Written beside it and renamed over it, so a crash never leaves half a config
+    let temp_path = config_path.with_extension("tmp");
+    fs::write(&temp_path, content)?;
+    if let Ok(meta) = fs::metadata(&config_path) {
+        fs::set_permissions(&temp_path, meta.permissions())?;
+    }
+    fs::rename(&temp_path, &config_path)?;
+    Ok(())
+}
+
+/// A state, lock or PID file kept next to the config file.
+fn get_profile_path(name: &str) -> Result<PathBuf> {
     let config_path = get_config_path()?;
-    let content = toml::to_string_pretty(config)?;
-    fs::write(config_path, content)?;
+    Ok(config_path.with_file_name(profile_file_name(&config_path, name)))
+}
+
+/// Name of the auxiliary file `name` for the config at `config_path`. The
+/// default `config.*` keeps the plain name; other configs prefix it with
+/// their file stem (`prod.toml` gets `prod.state.json`), so daemons for
+/// several configs in one directory don't share state, locks or PID files.
+fn profile_file_name(config_path: &std::path::Path, name: &str) -> String {
+    match config_path.file_stem().and_then(|s| s.to_str()) {
+        None | Some("config") => name.to_string(),
+        Some(stem) => format!("{}.{}", stem, name),
+    }
+}
+
+fn get_state_path() -> Result<PathBuf> {
+    get_profile_path("state.json")
+}
+
+fn load_state() -> Result<AppState> {
+    let state_path = get_state_path()?;
+    if !state_path.exists() {
+        return Ok(AppState::default());
+    }
+    let content = fs::read_to_string(&state_path)?;
+    let state: AppState = serde_json::from_str(&content)?;
+    Ok(state)
+}
+
+/// Writes the state beside the file and renames it into place, so readers
+/// that don't take the lock never see half of it.
+fn save_state(state: &AppState) -> Result<()> {
+    let state_path = get_state_path()?;
+    let content = serde_json::to_string_pretty(state)?;
+    let temp_path = state_path.with_extension("json.tmp");
+    fs::write(&temp_path, content)?;
+    fs::rename(&temp_path, &state_path)?;
+    Ok(())
+}
+
+/// Loads, changes and saves the state file. Backups running concurrently
+/// in this process take turns, and a lock file keeps other processes (a
+/// manual run beside the daemon) out, so none of their updates are lost.
+fn update_state(change: impl FnOnce(&mut AppState)) -> Result<()> {
+    static LOCK: std::sync::Mutex<()> = std::sync::Mutex::new(());
+    let _guard = LOCK.lock().unwrap_or_else(|e| e.into_inner());
+    let lock_path = get_profile_path("state.lock")?;
+    let lock = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&lock_path)
+        .with_context(|| format!("Failed to open lock file {:?}", lock_path))?;
+    lock.lock()?;
+    let mut state = load_state()?;
+    change(&mut state);
+    save_state(&state)
+}
+
+fn get_pid_path() -> Result<PathBuf> {
+    get_profile_path("daemon.pid")
+}
+
+fn get_daemon_status_path() -> Result<PathBuf> {
+    get_profile_path("daemon-status.json")
+}
+
+fn save_daemon_status(status: &DaemonStatus) -> Result<()> {
+    let content = serde_json::to_string_pretty(status)?;
+    fs::write(get_daemon_status_path()?, content)?;
     Ok(())
 }
 
+fn process_is_running(pid: u32) -> bool {
+    #[cfg(unix)]
+    {
+        Command::new("kill")
+            .arg("-0")
+            .arg(pid.to_string())
+            .stderr(std::process::Stdio::null())
+            .status()
+            .is_ok_and(|s| s.success())
+    }
+    #[cfg(not(unix))]
+    {
+        Command::new("tasklist")
+            .args(["/FI", &format!("PID eq {}", pid), "/NH"])
+            .output()
+            .is_ok_and(|o| String::from_utf8_lossy(&o.stdout).contains(&pid.to_string()))
+    }
+}
+
 fn find_db_index(query: &str, databases: &[DatabaseConfig]) -> Result<usize> {
     // Try to parse as ID (1-based index)
     if let Ok(id) = query.parse::<usize>() {
@@ -127,6 +766,38 @@ fn find_db_index(query: &str, databases: &[DatabaseConfig]) -> Result<usize> {
         return Ok(idx);
     }
 
+    // Fall back to case-insensitive exact, prefix, then substring matches
+    let query_lower = query.to_lowercase();
+    let matchers: [&dyn Fn(&str) -> bool; 3] = [
+        &|name| name == query_lower,
+        &|name| name.starts_with(&query_lower),
+        &|name| name.contains(&query_lower),
+    ];
+    for matches in matchers {
+        let candidates: Vec<usize> = databases
+            .iter()
+            .enumerate()
+            .filter(|(_, db)| matches(&db.name.to_lowercase()))
+            .map(|(i, _)| i)
+            .collect();
+
+        match candidates.len() {
+            0 => continue,
+            1 => return Ok(candidates[0]),
+            _ => {
+                let names: Vec<&str> = candidates
+                    .iter()
+                    .map(|&i| databases[i].name.as_str())
+                    .collect();
+                anyhow::bail!(
+                    "'{}' matches multiple configurations: {}",
+                    query,
+                    names.join(", ")
+                );
+            }
+        }
+    }
+
     anyhow::bail!("Database configuration not found: '{}'", query);
 }
 
@@ -136,7 +807,7 @@ async fn command_start(query: String) -> Result<()> {
 
     config.databases[idx].enabled = true;
 
-    save_config(&config)?;
+    save_config_unprompted(&config)?;
     info!(
         "Enabled backup for database: {}",
         config.databases[idx].name
@@ -150,7 +821,7 @@ async fn command_stop(query: String) -> Result<()> {
 
     config.databases[idx].enabled = false;
 
-    save_config(&config)?;
+    save_config_unprompted(&config)?;
     info!(
         "Disabled backup for database: {}",
         config.databases[idx].name
@@ -158,6 +829,96 @@ async fn command_stop(query: String) -> Result<()> {
     Ok(())
 }
 
+async fn command_schedule(query: String, clear: bool) -> Result<()> {
+    let mut config = load_config()?;
+    let idx = find_db_index(&query, &config.databases)?;
+    let db = &mut config.databases[idx];
+
+    if clear {
+        db.schedule = None;
+    } else {
+        println!(
+            "Current Schedule: {}",
+            db.schedule.clone().unwrap_or_else(|| "None".to_string())
+        );
+        db.schedule = Some(get_schedule_input()?);
+    }
+
+    let name = db.name.clone();
+    let schedule = db.schedule.clone();
+    save_config_unprompted(&config)?;
+    match schedule {
+        Some(schedule) => info!("Schedule for {} set to: {}", name, schedule),
+        None => info!("Schedule cleared for {}; it will only run manually", name),
+    }
+    Ok(())
+}
+
+async fn command_set_password(
+    query: String,
+    password_stdin: bool,
+    clear: bool,
+    empty: bool,
+) -> Result<()> {
+    let mut config = load_config()?;
+    let idx = find_db_index(&query, &config.databases)?;
+    let db = &mut config.databases[idx];
+
+    db.connection.password = if clear {
+        None
+    } else if empty {
+        Some(String::new())
+    } else if password_stdin {
+        read_password_stdin()?
+    } else {
+        Some(
+            Password::with_theme(&ColorfulTheme::default())
+                .with_prompt(format!("New password for {}", db.connection.user))
+                .with_confirmation("Repeat password", "Passwords don't match")
+                .allow_empty_password(true)
+                .interact()?,
+        )
+        .filter(|p| !p.is_empty())
+    };
+    if let Some(var) = &db.connection_url_env {
+        warn!(
+            "{} takes its connection from {}, so the stored password is not used",
+            db.name, var
+        );
+    }
+
+    let name = db.name.clone();
+    let password = db.connection.password.clone();
+    save_config(&config)?;
+    match password.as_deref() {
+        None => info!("Password cleared for {}", name),
+        Some("") => info!("Password set to empty for {}", name),
+        Some(_) => info!("Password updated for {}", name),
+    }
+    Ok(())
+}
+
+/// Re-encrypts the stored passwords under a new passphrase and salt. The
+/// config is only rewritten once every password has been decrypted, so a
+/// wrong current passphrase leaves it as it was.
+fn command_rotate_key() -> Result<()> {
+    let config_path = get_config_path()?;
+    if !config_path.exists() {
+        anyhow::bail!("Config file {:?} does not exist", config_path);
+    }
+    let mut config = load_config()?;
+    if config.plaintext_passwords {
+        anyhow::bail!("plaintext_passwords is set, so stored passwords aren't encrypted");
+    }
+    let count = secrets::rotate(&mut config)?;
+    save_config(&config)?;
+    success!(
+        "Re-encrypted {} stored password(s) under the new passphrase",
+        count
+    );
+    Ok(())
+}
+
 fn get_schedule_input() -> Result<String> {
     let options = vec![
         "Every Minute (Test)",
@@ -255,26 +1016,29 @@ fn get_schedule_input() -> Result<String> {
         5 => {
             // Custom
             let schedule: String = Input::with_theme(&ColorfulTheme::default())
-                .with_prompt("Enter Cron Expression")
+                .with_prompt(
+                    "Enter Cron Expression (sec min hour day month weekday; \
+                     a 5-field crontab expression runs at second 0)",
+                )
                 .default("0 0 * * * *".into())
                 .validate_with(|input: &String| -> Result<(), &str> {
-                    if Schedule::from_str(input).is_ok() {
+                    if Schedule::from_str(&models::normalize_schedule(input)).is_ok() {
                         Ok(())
                     } else {
                         Err("Invalid cron expression")
                     }
                 })
                 .interact_text()?;
-            Ok(schedule)
+            Ok(models::normalize_schedule(&schedule))
         }
         _ => unreachable!(),
     }
 }
 
-async fn command_add() -> Result<()> {
+async fn command_add(password_stdin: bool) -> Result<()> {
     println!("Adding a new database configuration...");
 
-    let db_types = vec![DbType::MariaDB, DbType::PostgreSQL];
+    let db_types = vec![DbType::MariaDB, DbType::PostgreSQL, DbType::SQLite];
     let selection = Select::with_theme(&ColorfulTheme::default())
         .with_prompt("Select Database Type")
         .default(0)
@@ -286,33 +1050,26 @@ async fn command_add() -> Result<()> {
         .with_prompt("Configuration Name (e.g. production-db)")
         .interact_text()?;
 
-    let host: String = Input::with_theme(&ColorfulTheme::default())
-        .with_prompt("Host")
-        .default("localhost".into())
-        .interact_text()?;
-
-    let port: u16 = Input::with_theme(&ColorfulTheme::default())
-        .with_prompt("Port")
-        .default(match db_type {
-            DbType::MariaDB => 3306,
-            DbType::PostgreSQL => 5432,
-        })
-        .interact_text()?;
-
-    let user: String = Input::with_theme(&ColorfulTheme::default())
-        .with_prompt("User")
+    let description: String = Input::with_theme(&ColorfulTheme::default())
+        .with_prompt("Description (optional)")
+        .allow_empty(true)
         .interact_text()?;
 
-    let password: Option<String> = Password::with_theme(&ColorfulTheme::default())
-        .with_prompt("Password (optional)")
-        .allow_empty_password(true)
-        .interact()
-        .ok()
-        .filter(|p| !p.is_empty());
-
-    let database: String = Input::with_theme(&ColorfulTheme::default())
-        .with_prompt("Database Name")
-        .interact_text()?;
+    // A SQLite database is a file on this machine, with no server to log in to
+    let connection = if db_type == DbType::SQLite {
+        ConnectionDetails {
+            database: Input::with_theme(&ColorfulTheme::default())
+                .with_prompt("Database file path")
+                .interact_text()?,
+            ..ConnectionDetails::default()
+        }
+    } else {
+        let mut connection = prompt_server_connection(&db_type, password_stdin)?;
+        connection.database = Input::with_theme(&ColorfulTheme::default())
+            .with_prompt("Database Name")
+            .interact_text()?;
+        connection
+    };
 
     let output_dir_str: String = Input::with_theme(&ColorfulTheme::default())
         .with_prompt("Output Directory for Backups")
@@ -325,489 +1082,5335 @@ async fn command_add() -> Result<()> {
         .default(5)
         .interact_text()?;
 
+    let compression = prompt_compression(Compression::Gzip)?;
+
     let schedule = get_schedule_input()?;
 
-    let mut config = load_config()?;
-    let new_db_config = DatabaseConfig {
+    let mut new_db_config = DatabaseConfig::new(
         name,
         db_type,
-        connection: ConnectionDetails {
-            host,
-            port,
-            user,
-            password,
-            database,
-        },
+        connection,
         output_dir,
         retention_count,
-        schedule: Some(schedule),
-        enabled: true,
-    };
+        Some(schedule),
+    );
+    new_db_config.description = Some(description.trim().to_string()).filter(|d| !d.is_empty());
+    new_db_config.compression = compression;
+    prompt_retention_days(&mut new_db_config)?;
+
+    if Confirm::with_theme(&ColorfulTheme::default())
+        .with_prompt("Configure advanced options?")
+        .default(false)
+        .interact()?
+    {
+        prompt_advanced_options(&mut new_db_config)?;
+    }
+
+    let mut config = load_config()?;
 
-    config.databases.push(new_db_config);
+    config.databases.push(new_db_config.clone());
     save_config(&config)?;
 
     println!("Configuration saved successfully!");
+
+    if Confirm::with_theme(&ColorfulTheme::default())
+        .with_prompt("Test the connection now?")
+        .default(true)
+        .interact()?
+    {
+        match test_connection(&new_db_config) {
+            Ok(()) => success!("Connection to {} works", new_db_config.name),
+            Err(e) => {
+                error!("Connection to {} failed: {:#}", new_db_config.name, e);
+                println!(
+                    "The configuration was saved anyway; fix it with `dbr edit {}`.",
+                    new_db_config.name
+                );
+            }
+        }
+    }
     Ok(())
 }
 
-fn command_list() -> Result<()> {
-    let config = load_config()?;
-    if config.databases.is_empty() {
-        println!("No databases configured.");
+async fn command_init(password_stdin: bool) -> Result<()> {
+    let mut config = load_config()?;
+    if !config.databases.is_empty()
+        && !Confirm::with_theme(&ColorfulTheme::default())
+            .with_prompt(format!(
+                "{} database(s) are already configured. Add more?",
+                config.databases.len()
+            ))
+            .default(true)
+            .interact()?
+    {
         return Ok(());
     }
 
-    let mut table = Table::new();
-    table.load_preset(comfy_table::presets::UTF8_FULL);
-    table.set_header(vec![
-        "ID",
-        "Name",
-        "Type",
-        "Host",
-        "Database",
-        "Schedule",
-        "Retention",
-        "Status",
-        "Last Backup",
-    ]);
+    println!("Setting up backups for a database server...");
 
-    for (i, db) in config.databases.iter().enumerate() {
-        let last_backup = get_last_backup(db)
-            .and_then(|p| p.file_name().map(|n| n.to_string_lossy().to_string()))
-            .unwrap_or_else(|| "Never".to_string());
+    let db_types = vec![DbType::MariaDB, DbType::PostgreSQL];
+    let selection = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Select Database Type")
+        .default(0)
+        .items(&db_types)
+        .interact()?;
+    let db_type = db_types[selection].clone();
 
-        let status_cell = if db.enabled {
-            Cell::new("Enabled").fg(Color::Green)
-        } else {
-            Cell::new("Disabled").fg(Color::Red)
-        };
+    let connection = prompt_server_connection(&db_type, password_stdin)?;
 
-        table.add_row(vec![
-            Cell::new((i + 1).to_string()),
-            Cell::new(&db.name),
-            Cell::new(db.db_type.to_string()),
-            Cell::new(&db.connection.host),
-            Cell::new(&db.connection.database),
-            Cell::new(db.schedule.clone().unwrap_or_else(|| "None".to_string())),
-            Cell::new(db.retention_count.to_string()),
-            status_cell,
-            Cell::new(last_backup),
-        ]);
+    let discover = Confirm::with_theme(&ColorfulTheme::default())
+        .with_prompt("Discover databases on this server?")
+        .default(true)
+        .interact()?;
+    let discovered = if discover {
+        match client::list_databases(&db_type, &connection, false) {
+            Ok(names) if !names.is_empty() => Some(names),
+            Ok(_) => {
+                warn!("No user databases found on {}", connection.host);
+                None
+            }
+            Err(e) => {
+                warn!("Could not list databases: {}", e);
+                None
+            }
+        }
+    } else {
+        None
+    };
+
+    let databases = match discovered {
+        Some(names) => {
+            let picked = MultiSelect::with_theme(&ColorfulTheme::default())
+                .with_prompt("Select databases to back up (space to toggle, enter to confirm)")
+                .items(&names)
+                .defaults(&vec![true; names.len()])
+                .interact()?;
+            picked.into_iter().map(|i| names[i].clone()).collect()
+        }
+        None => {
+            let list: String = Input::with_theme(&ColorfulTheme::default())
+                .with_prompt("Database names (comma separated)")
+                .interact_text()?;
+            list.split(',')
+                .map(str::trim)
+                .filter(|n| !n.is_empty())
+                .map(String::from)
+                .collect::<Vec<String>>()
+        }
+    };
+
+    if databases.is_empty() {
+        println!("No databases selected.");
+        return Ok(());
     }
 
-    println!("{table}");
+    add_server_databases(&mut config, &db_type, &connection, &databases)?;
+    save_config(&config)?;
     Ok(())
 }
 
-async fn command_delete(target_name: Option<String>) -> Result<()> {
-    let mut config = load_config()?;
-    if config.databases.is_empty() {
-        println!("No databases configured.");
+async fn command_discover(args: DiscoverArgs) -> Result<()> {
+    if args.db_type == DbType::SQLite {
+        anyhow::bail!(
+            "SQLite has no server to discover databases on; add each file with `dbr add`"
+        );
+    }
+    let password = if args.password_stdin {
+        read_password_stdin()?
+    } else {
+        Some(
+            Password::with_theme(&ColorfulTheme::default())
+                .with_prompt(format!(
+                    "Password for {}@{} (optional)",
+                    args.user, args.host
+                ))
+                .allow_empty_password(true)
+                .interact()?,
+        )
+        .filter(|p| !p.is_empty())
+    };
+    let connection = ConnectionDetails {
+        port: args.port.unwrap_or_else(|| args.db_type.default_port()),
+        host: args.host,
+        user: args.user,
+        password,
+        database: String::new(),
+    };
+
+    let mut config = load_config()?;
+    let names: Vec<String> = client::list_databases(&args.db_type, &connection, false)
+        .with_context(|| format!("Failed to list databases on {}", connection.host))?
+        .into_iter()
+        .filter(|name| {
+            // Already backed up under some config
+            !config.databases.iter().any(|db| {
+                db.db_type == args.db_type
+                    && db.connection.host == connection.host
+                    && db.connection.port == connection.port
+                    && db.connection.database == *name
+            })
+        })
+        .collect();
+
+    if names.is_empty() {
+        println!("No new databases found on {}.", connection.host);
         return Ok(());
     }
 
-    let selection_idx = if let Some(query) = target_name {
-        find_db_index(&query, &config.databases)?
-    } else {
-        // Explicitly add an "Exit" option
-        let mut options: Vec<String> = config
-            .databases
-            .iter()
-            .enumerate()
-            .map(|(i, db)| format!("{}. {} ({})", i + 1, db.name, db.db_type))
-            .collect();
-        options.push("Cancel / Exit".to_string());
+    let picked = MultiSelect::with_theme(&ColorfulTheme::default())
+        .with_prompt("Select databases to add (space to toggle, enter to confirm)")
+        .items(&names)
+        .interact()?;
+    if picked.is_empty() {
+        println!("No databases selected.");
+        return Ok(());
+    }
+    let databases: Vec<String> = picked.into_iter().map(|i| names[i].clone()).collect();
 
-        let idx = Select::with_theme(&ColorfulTheme::default())
-            .with_prompt("Select configuration to delete")
-            .items(&options)
-            .default(0)
-            .interact()?;
+    add_server_databases(&mut config, &args.db_type, &connection, &databases)?;
+    save_config(&config)?;
+    println!("Configuration saved successfully!");
+    Ok(())
+}
 
-        // Check if the user selected default Cancel / Exit
-        if idx == options.len() - 1 {
-            println!("Deletion cancelled.");
-            return Ok(());
+/// Prompts once for output directory, retention and schedule, then adds a
+/// config named after each database, backing up into `<output dir>/<name>`.
+fn add_server_databases(
+    config: &mut AppConfig,
+    db_type: &DbType,
+    connection: &ConnectionDetails,
+    databases: &[String],
+) -> Result<()> {
+    let output_dir_str: String = Input::with_theme(&ColorfulTheme::default())
+        .with_prompt("Base Output Directory (one subdirectory per database)")
+        .default("./backups".into())
+        .interact_text()?;
+    let output_base = PathBuf::from(output_dir_str);
+
+    let retention_count: usize = Input::with_theme(&ColorfulTheme::default())
+        .with_prompt("Retention Count (number of backups to keep)")
+        .default(5)
+        .interact_text()?;
+
+    let schedule = get_schedule_input()?;
+
+    for database in databases {
+        if config.databases.iter().any(|db| db.name == *database) {
+            warn!(
+                "Skipping '{}': a configuration with that name exists",
+                database
+            );
+            continue;
         }
-        idx
-    };
+        let mut conn = connection.clone();
+        conn.database = database.clone();
+        config.databases.push(DatabaseConfig::new(
+            database.clone(),
+            db_type.clone(),
+            conn,
+            output_base.join(database),
+            retention_count,
+            Some(schedule.clone()),
+        ));
+        println!("Added configuration: {}", database);
+    }
+    Ok(())
+}
 
-    if Confirm::with_theme(&ColorfulTheme::default())
-        .with_prompt(format!(
-            "Are you sure you want to delete '{}'?",
-            config.databases[selection_idx].name
-        ))
-        .interact()?
-    {
-        config.databases.remove(selection_idx);
-        save_config(&config)?;
-        println!("Configuration deleted.");
-    } else {
-        println!("Deletion cancelled.");
+fn prompt_compression(current: Compression) -> Result<Compression> {
+    let options = [Compression::None, Compression::Gzip];
+    let labels = ["None (.sql)", "Gzip (.sql.gz)"];
+    let selection = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Compression")
+        .items(&labels)
+        .default(options.iter().position(|c| *c == current).unwrap_or(0))
+        .interact()?;
+    Ok(options[selection])
+}
+
+/// Asks for `retention_days` and, if one is given, how it combines with
+/// `retention_count`. Replaces a `retention_age` set in the config file.
+fn prompt_retention_days(db: &mut DatabaseConfig) -> Result<()> {
+    let current = db
+        .retention_days
+        .map(|d| d.to_string())
+        .or_else(|| db.retention_age.clone())
+        .unwrap_or_default();
+    let days: String = Input::with_theme(&ColorfulTheme::default())
+        .with_prompt("Retention Days (optional, leave empty to keep by count only)")
+        .default(current.clone())
+        .allow_empty(true)
+        .validate_with(|s: &String| -> Result<(), String> {
+            if s.trim().is_empty() || *s == current || s.trim().parse::<u64>().is_ok() {
+                Ok(())
+            } else {
+                Err("Enter a whole number of days".to_string())
+            }
+        })
+        .interact_text()?;
+    let days = days.trim();
+    if days == current {
+        return Ok(());
+    }
+    db.retention_age = None;
+    db.retention_days = days.parse().ok();
+    if db.retention_days.is_none() {
+        return Ok(());
     }
 
+    let options = [RetentionPolicy::Either, RetentionPolicy::Both];
+    let labels = [
+        "Delete backups beyond the count or older than that",
+        "Keep everything younger than that, even beyond the count",
+    ];
+    let selection = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Retention policy")
+        .items(&labels)
+        .default(
+            options
+                .iter()
+                .position(|p| *p == db.retention_policy)
+                .unwrap_or(0),
+        )
+        .interact()?;
+    db.retention_policy = options[selection];
     Ok(())
 }
 
-async fn command_edit(target_name: Option<String>) -> Result<()> {
-    // Check if there are any configurations to edit
-    let mut config = load_config()?;
-    if config.databases.is_empty() {
-        println!("No databases configured.");
+/// The "advanced" part of the add wizard: settings most users leave alone.
+fn prompt_advanced_options(db: &mut DatabaseConfig) -> Result<()> {
+    if db.db_type == DbType::MariaDB {
+        let objects = ["Stored routines", "Triggers", "Events"];
+        let included = MultiSelect::with_theme(&ColorfulTheme::default())
+            .with_prompt("Database objects to include (space to toggle, enter to confirm)")
+            .items(&objects)
+            .defaults(&[db.include_routines, db.include_triggers, db.include_events])
+            .interact()?;
+        db.include_routines = included.contains(&0);
+        db.include_triggers = included.contains(&1);
+        db.include_events = included.contains(&2);
+    } else if db.db_type == DbType::PostgreSQL {
+        println!("pg_dump always includes functions and triggers.");
+    }
+
+    // Must match the database's real encoding, or the dump comes out garbled
+    let charset: String = Input::with_theme(&ColorfulTheme::default())
+        .with_prompt("Character set (must match the database's encoding)")
+        .default(db.charset().to_string())
+        .interact_text()?;
+    db.charset = Some(charset).filter(|c| c != db.db_type.default_charset());
+
+    prompt_tables(db)?;
+    prompt_extra_args(db)
+}
+
+/// Asks for extra dump tool options, split like a shell command line.
+fn prompt_extra_args(db: &mut DatabaseConfig) -> Result<()> {
+    let line: String = Input::with_theme(&ColorfulTheme::default())
+        .with_prompt("Extra dump tool options (e.g. --hex-blob; empty for none)")
+        .default(shell_words::join(&db.extra_args))
+        .allow_empty(true)
+        .validate_with(|line: &String| {
+            shell_words::split(line)
+                .map(|_| ())
+                .map_err(|e| e.to_string())
+        })
+        .interact_text()?;
+    db.extra_args = shell_words::split(&line)?;
+    Ok(())
+}
+
+/// Asks which tables to back up, as comma-separated lists. Tables to
+/// include take precedence: the exclude list is only asked for without them.
+fn prompt_tables(db: &mut DatabaseConfig) -> Result<()> {
+    let list = |prompt: &str, current: &[String]| -> Result<Vec<String>> {
+        let line: String = Input::with_theme(&ColorfulTheme::default())
+            .with_prompt(prompt)
+            .default(current.join(", "))
+            .allow_empty(true)
+            .interact_text()?;
+        Ok(line
+            .split(',')
+            .map(|t| t.trim().to_string())
+            .filter(|t| !t.is_empty())
+            .collect())
+    };
+    db.include_tables = list(
+        "Tables to back up (comma-separated; empty for all)",
+        &db.include_tables,
+    )?;
+    if !db.include_tables.is_empty() {
+        db.exclude_tables.clear();
+        return Ok(());
+    }
+    if db.db_type == DbType::SQLite {
         return Ok(());
     }
+    db.exclude_tables = list(
+        "Tables to leave out (comma-separated; empty for none)",
+        &db.exclude_tables,
+    )?;
+    Ok(())
+}
 
-    let selection_idx = if let Some(query) = target_name {
-        find_db_index(&query, &config.databases)?
-    } else {
-        // Select which configuration to edit
-        let mut options: Vec<String> = config
-            .databases
-            .iter()
-            .enumerate()
-            .map(|(i, db)| format!("{}. {} ({})", i + 1, db.name, db.db_type))
-            .collect();
-        options.push("Cancel / Exit".to_string());
+/// Prompts for host, port and credentials, leaving the database name empty.
+fn prompt_server_connection(db_type: &DbType, password_stdin: bool) -> Result<ConnectionDetails> {
+    let host: String = Input::with_theme(&ColorfulTheme::default())
+        .with_prompt("Host")
+        .default("localhost".into())
+        .interact_text()?;
 
-        let idx = Select::with_theme(&ColorfulTheme::default())
-            .with_prompt("Select configuration to edit")
-            .items(&options)
-            .default(0)
-            .interact()?;
+    let port: u16 = Input::with_theme(&ColorfulTheme::default())
+        .with_prompt("Port")
+        .default(db_type.default_port())
+        .interact_text()?;
 
-        if idx == options.len() - 1 {
-            println!("Exiting edit mode.");
-            return Ok(());
-        }
-        idx
+    let user: String = Input::with_theme(&ColorfulTheme::default())
+        .with_prompt("User")
+        .interact_text()?;
+
+    let password = if password_stdin {
+        read_password_stdin()?
+    } else {
+        Password::with_theme(&ColorfulTheme::default())
+            .with_prompt("Password (optional)")
+            .allow_empty_password(true)
+            .interact()
+            .ok()
+            .filter(|p| !p.is_empty())
     };
 
-    let db = &mut config.databases[selection_idx];
+    Ok(ConnectionDetails {
+        host,
+        port,
+        user,
+        password,
+        database: String::new(),
+    })
+}
 
-    // Select which field to edit
-    let fields = vec![
+/// Reads a password from the first line of stdin, as piped in by CI, so it
+/// never appears in shell history or process arguments. An empty line means
+/// no password.
+fn read_password_stdin() -> Result<Option<String>> {
+    let mut line = String::new();
+    std::io::stdin()
+        .read_line(&mut line)
+        .context("Failed to read password from stdin")?;
+    let password = line.trim_end_matches(['\r', '\n']);
+    Ok(Some(password.to_string()).filter(|p| !p.is_empty()))
+}
+
+fn command_list(utc: bool, verbose: bool) -> Result<()> {
+    let config = load_config()?;
+    if config.databases.is_empty() {
+        println!("No databases configured.");
+        return Ok(());
+    }
+
+    // Schedules are evaluated in local time by the daemon; --utc only changes the display
+    let next_run_header = if utc {
+        "Next Run (UTC)".to_string()
+    } else {
+        format!("Next Run (Local, {})", Local::now().format("%:z"))
+    };
+
+    let mut table = new_table();
+    let mut header = vec![
+        "ID",
         "Name",
+        "Type",
         "Host",
-        "Port",
-        "User",
-        "Password",
         "Database",
-        "Output Directory",
-        "Retention Count",
         "Schedule",
-        "Exit Edit Mode",
+        &next_run_header,
+        "Retention",
+        "Status",
+        "Last Backup",
     ];
+    if verbose {
+        header.push("Checksum");
+        header.push("Description");
+    }
+    table.set_header(header);
 
-    loop {
-        let field_selection = Select::with_theme(&ColorfulTheme::default())
-            .with_prompt(format!("Editing '{}'. Select field to change", db.name))
-            .items(&fields)
-            .default(fields.len() - 1) // Default to Exit
-            .interact()?;
+    for (i, db) in config.databases.iter().enumerate() {
+        let last_path = get_last_backup(db);
+        let last_backup = last_path
+            .as_ref()
+            .and_then(|p| p.file_name().map(|n| n.to_string_lossy().to_string()))
+            .unwrap_or_else(|| "Never".to_string());
 
-        match field_selection {
-            0 => {
-                // Name
-                db.name = Input::with_theme(&ColorfulTheme::default())
-                    .with_prompt("Name")
-                    .default(db.name.clone())
-                    .interact_text()?;
-            }
-            1 => {
-                // Host
-                db.connection.host = Input::with_theme(&ColorfulTheme::default())
-                    .with_prompt("Host")
-                    .default(db.connection.host.clone())
-                    .interact_text()?;
-            }
-            2 => {
-                // Port
-                db.connection.port = Input::with_theme(&ColorfulTheme::default())
-                    .with_prompt("Port")
-                    .default(db.connection.port)
-                    .interact_text()?;
-            }
-            3 => {
-                // User
-                db.connection.user = Input::with_theme(&ColorfulTheme::default())
-                    .with_prompt("User")
-                    .default(db.connection.user.clone())
-                    .interact_text()?;
-            }
-            4 => {
-                // Password
-                let new_pass = Password::with_theme(&ColorfulTheme::default())
-                    .with_prompt("Password (leave empty to keep unchanged, type 'clear' to remove)")
-                    .allow_empty_password(true)
-                    .interact()?;
+        let status_cell = if db.enabled {
+            Cell::new("Enabled").fg(Color::Green)
+        } else {
+            Cell::new("Disabled").fg(Color::Red)
+        };
 
-                if new_pass == "clear" {
-                    db.connection.password = None;
-                } else if !new_pass.is_empty() {
-                    db.connection.password = Some(new_pass);
+        let next_run = db
+            .schedule
+            .as_deref()
+            .and_then(|s| Schedule::from_str(s).ok())
+            .and_then(|s| s.upcoming(Local).next())
+            .map(|t| {
+                if utc {
+                    t.with_timezone(&chrono::Utc)
+                        .format("%Y-%m-%d %H:%M:%S")
+                        .to_string()
+                } else {
+                    t.format("%Y-%m-%d %H:%M:%S").to_string()
                 }
-            }
-            5 => {
-                // Database
-                db.connection.database = Input::with_theme(&ColorfulTheme::default())
-                    .with_prompt("Database")
-                    .default(db.connection.database.clone())
-                    .interact_text()?;
-            }
-            6 => {
-                // Output Dir
-                let path_str = Input::with_theme(&ColorfulTheme::default())
-                    .with_prompt("Output Directory")
-                    .default(db.output_dir.to_string_lossy().to_string())
-                    .interact_text()?;
-                db.output_dir = PathBuf::from(path_str);
-            }
-            7 => {
-                // Retention
-                db.retention_count = Input::with_theme(&ColorfulTheme::default())
-                    .with_prompt("Retention Count")
-                    .default(db.retention_count)
-                    .interact_text()?;
-            }
-            8 => {
-                // Schedule
-                println!(
-                    "Current Schedule: {}",
-                    db.schedule.clone().unwrap_or_else(|| "None".to_string())
-                );
-                let new_schedule = get_schedule_input()?;
-                db.schedule = Some(new_schedule);
-            }
-            9 => break, // Exit
-            _ => unreachable!(),
+            })
+            .unwrap_or_else(|| "-".to_string());
+
+        let mut database = match &db.database_pattern {
+            Some(pattern) => format!("{} (pattern)", pattern),
+            None => db.connection.database.clone(),
+        };
+        if !db.include_tables.is_empty() {
+            database.push_str(&format!(" ({})", db.include_tables.join(", ")));
         }
-    }
+        if !db.exclude_tables.is_empty() {
+            database.push_str(&format!(" (without {})", db.exclude_tables.join(", ")));
+        }
+        if let Some(clause) = &db.where_clause {
+            database.push_str(&format!("\nWHERE {}", clause));
+        }
+
+        let mut row = vec![
+            Cell::new((i + 1).to_string()),
+            Cell::new(&db.name),
+            Cell::new(db.db_type.to_string()),
+            Cell::new(&db.connection.host),
+            Cell::new(database),
+            Cell::new(db.schedule.clone().unwrap_or_else(|| "None".to_string())),
+            Cell::new(next_run),
+            Cell::new(db.retention_summary()),
+            status_cell,
+            Cell::new(last_backup),
+        ];
+        if verbose {
+            let checksum = match last_path.as_deref().map(manifest::verify_checksum) {
+                Some(Ok(Some(true))) => Cell::new("OK").fg(Color::Green),
+                Some(Ok(Some(false))) => Cell::new("MISMATCH").fg(Color::Red),
+                Some(Err(_)) => Cell::new("Unreadable").fg(Color::Red),
+                Some(Ok(None)) | None => Cell::new("-"),
+            };
+            row.push(checksum);
+            row.push(Cell::new(db.description.as_deref().unwrap_or("-")));
+        }
+        table.add_row(row);
+    }
+
+    println!("{table}");
+    Ok(())
+}
+
+fn command_daemon_status() -> Result<()> {
+    let pid_path = get_pid_path()?;
+    let pid = fs::read_to_string(&pid_path)
+        .ok()
+        .and_then(|p| p.trim().parse::<u32>().ok());
+
+    let Some(pid) = pid.filter(|&pid| process_is_running(pid)) else {
+        if pid_path.exists() {
+            println!("Daemon is not running (stale PID file at {:?}).", pid_path);
+        } else {
+            println!("Daemon is not running.");
+        }
+        std::process::exit(1);
+    };
+
+    println!("Daemon is running (PID {}).", pid);
+    let status: Option<DaemonStatus> = fs::read_to_string(get_daemon_status_path()?)
+        .ok()
+        .and_then(|c| serde_json::from_str(&c).ok());
+    if let Some(status) = status.filter(|s| s.pid == pid) {
+        let uptime = Local::now() - status.started_at;
+        println!(
+            "Started:  {} (up {}h {}m)",
+            status.started_at.format("%Y-%m-%d %H:%M:%S"),
+            uptime.num_hours(),
+            uptime.num_minutes() % 60
+        );
+        println!(
+            "Backups:  {} this session ({} failed)",
+            status.backups, status.failures
+        );
+    }
+    Ok(())
+}
+
+/// Health of one config, as reported by `dbr status`.
+#[derive(serde::Serialize)]
+struct DatabaseHealth {
+    name: String,
+    enabled: bool,
+    schedule: Option<String>,
+    last_backup: Option<String>,
+    last_backup_at: Option<chrono::DateTime<Local>>,
+    size_bytes: Option<u64>,
+    /// Seconds since the config last backed up successfully (or found
+    /// nothing had changed)
+    age_seconds: Option<i64>,
+    last_failure_at: Option<chrono::DateTime<Local>>,
+    /// Enabled and scheduled, but nothing successful for longer than the
+    /// schedule's interval
+    stale: bool,
+}
+
+fn database_health(db: &DatabaseConfig, state: &AppState, jitter: u64) -> DatabaseHealth {
+    let now = Local::now();
+    let last_path = get_last_backup(db);
+    let last_backup_at = last_path
+        .as_ref()
+        .and_then(|p| backup_timestamp(db, p).and_local_timezone(Local).earliest());
+    let stats = state.databases.get(&db.name).map(|s| &s.stats);
+    // A deduplicated or pattern backup leaves no new file at the top level
+    let last_good = [
+        last_backup_at,
+        stats.and_then(|s| s.last_success),
+        stats.and_then(|s| s.last_unchanged),
+    ]
+    .into_iter()
+    .flatten()
+    .max();
+
+    // The gap between the two latest fire times, plus the jitter a run may be delayed by
+    let interval = db
+        .schedule
+        .as_deref()
+        .and_then(|s| Schedule::from_str(s).ok())
+        .and_then(|schedule| {
+            let mut previous = schedule.after(&now).rev();
+            let latest = previous.next()?;
+            Some(latest - previous.next()? + chrono::Duration::seconds(jitter as i64))
+        });
+    let stale =
+        db.enabled && interval.is_some_and(|interval| last_good.is_none_or(|t| now - t > interval));
+
+    DatabaseHealth {
+        name: db.name.clone(),
+        enabled: db.enabled,
+        schedule: db.schedule.clone(),
+        last_backup: last_path
+            .as_ref()
+            .and_then(|p| p.file_name())
+            .map(|n| n.to_string_lossy().to_string()),
+        last_backup_at,
+        size_bytes: last_path.as_ref().and_then(|p| split::total_size(p).ok()),
+        age_seconds: last_good.map(|t| (now - t).num_seconds()),
+        last_failure_at: stats.and_then(|s| s.last_failure),
+        stale,
+    }
+}
+
+fn command_status(json: bool) -> Result<()> {
+    let config = load_config()?;
+    let state = load_state()?;
+    let health: Vec<DatabaseHealth> = config
+        .databases
+        .iter()
+        .map(|db| {
+            let jitter = db.jitter_seconds.or(config.jitter_seconds).unwrap_or(0);
+            database_health(db, &state, jitter)
+        })
+        .collect();
+
+    if json {
+        println!("{}", serde_json::to_string_pretty(&health)?);
+    } else {
+        let mut table = new_table();
+        table.set_header(vec![
+            "Name",
+            "Status",
+            "Last Backup",
+            "Age",
+            "Size",
+            "Health",
+        ]);
+        for h in &health {
+            let status = if h.enabled {
+                Cell::new("Enabled").fg(Color::Green)
+            } else {
+                Cell::new("Disabled").fg(Color::Red)
+            };
+            let health_cell = if h.stale {
+                Cell::new("STALE").fg(Color::Red)
+            } else if h.enabled && h.schedule.is_some() {
+                Cell::new("OK").fg(Color::Green)
+            } else {
+                Cell::new("-")
+            };
+            table.add_row(vec![
+                Cell::new(&h.name),
+                status,
+                Cell::new(h.last_backup.as_deref().unwrap_or("Never")),
+                Cell::new(h.age_seconds.map_or("-".to_string(), |secs| {
+                    format_elapsed(Duration::from_secs(secs.max(0) as u64))
+                })),
+                Cell::new(h.size_bytes.map_or("-".to_string(), format_bytes)),
+                health_cell,
+            ]);
+        }
+        println!("{table}");
+    }
+
+    let stale = health.iter().filter(|h| h.stale).count();
+    if stale > 0 {
+        anyhow::bail!("{} database(s) have missed their schedule", stale);
+    }
+    Ok(())
+}
+
+fn command_gc() -> Result<()> {
+    let config_path = locate_config_path()?;
+    if !config_path.exists() {
+        // Without a config every entry would look stale
+        anyhow::bail!(
+            "No config at {:?}; refusing to clear the state",
+            config_path
+        );
+    }
+    let config = load_config()?;
+    let names: std::collections::BTreeSet<&str> =
+        config.databases.iter().map(|db| db.name.as_str()).collect();
+
+    let mut removed = Vec::new();
+    update_state(|state| removed = state.retain_configs(&names))?;
+    if removed.is_empty() {
+        println!("State is in sync with the config; nothing to remove.");
+        return Ok(());
+    }
+    for name in &removed {
+        info!("Removed state for deleted config '{}'", name);
+    }
+    success!("Removed state for {} deleted config(s)", removed.len());
+    Ok(())
+}
+
+fn command_stats(target_name: Option<String>) -> Result<()> {
+    let config = load_config()?;
+    let state = load_state()?;
+
+    let databases: Vec<&DatabaseConfig> = match target_name {
+        Some(query) => vec![&config.databases[find_db_index(&query, &config.databases)?]],
+        None => config.databases.iter().collect(),
+    };
+    if databases.is_empty() {
+        println!("No databases configured.");
+        return Ok(());
+    }
+
+    let mut table = new_table();
+    table.set_header(vec![
+        "Name",
+        "Backups",
+        "Failures",
+        "Total Written",
+        "Last Success",
+        "Last Failure",
+    ]);
+
+    let format_time = |t: Option<chrono::DateTime<Local>>| {
+        t.map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_else(|| "Never".to_string())
+    };
+
+    for db in databases {
+        let stats = state
+            .databases
+            .get(&db.name)
+            .map(|s| s.stats.clone())
+            .unwrap_or_default();
+
+        let failures_cell = if stats.failures > 0 {
+            Cell::new(stats.failures.to_string()).fg(Color::Red)
+        } else {
+            Cell::new(stats.failures.to_string())
+        };
+
+        table.add_row(vec![
+            Cell::new(&db.name),
+            Cell::new(stats.backups.to_string()),
+            failures_cell,
+            Cell::new(format_bytes(stats.bytes_written)),
+            Cell::new(format_time(stats.last_success)),
+            Cell::new(format_time(stats.last_failure)),
+        ]);
+    }
+
+    println!("{table}");
+    Ok(())
+}
+
+/// Reports whether two backups hold the same dump (ignoring mysqldump's date
+/// line) and, if not, which tables differ in schema or data. Exits with
+/// status 1 when they differ.
+fn command_compare(a: PathBuf, b: PathBuf) -> Result<()> {
+    for path in [&a, &b] {
+        if path.is_dir() {
+            anyhow::bail!(
+                "{:?} is a mydumper directory; only single-file dumps can be compared",
+                path
+            );
+        }
+    }
+    if content_hash(&a)? == content_hash(&b)? {
+        println!("Identical: {:?} and {:?} hold the same dump", a, b);
+        return Ok(());
+    }
+
+    let before = compare::summarize(client::open_backup(&a)?)?;
+    let after = compare::summarize(client::open_backup(&b)?)?;
+    let comparison = compare::compare(&before, &after);
+    println!("{:?} and {:?} differ", a, b);
+    if comparison.is_empty() {
+        println!("Only comments differ (e.g. server version or host)");
+    }
+    if !comparison.added.is_empty() {
+        println!("Tables added: {}", comparison.added.join(", "));
+    }
+    if !comparison.removed.is_empty() {
+        println!("Tables removed: {}", comparison.removed.join(", "));
+    }
+    if !comparison.schema_changed.is_empty() {
+        println!("Schema changed:");
+        for (table, lines) in &comparison.schema_changed {
+            println!("  {}", table);
+            for line in lines {
+                println!("    {}", line);
+            }
+        }
+    }
+    if !comparison.data_changed.is_empty() {
+        println!("Data changed: {}", comparison.data_changed.join(", "));
+    }
+    if comparison.other_changed {
+        println!("Other statements (views, routines, triggers, ...) differ");
+    }
+    std::process::exit(1);
+}
+
+/// What `verify` found for one backup.
+enum Verification {
+    Passed,
+    Failed(String),
+    /// Nothing recorded to check against
+    Unverified(String),
+}
+
+/// Checks a backup against its manifest: its size, and its content hash when
+/// one was recorded.
+fn verify_backup(path: &std::path::Path) -> Verification {
+    let Ok(manifest) = Manifest::read(path) else {
+        return Verification::Unverified("No manifest".to_string());
+    };
+    match split::total_size(path) {
+        Ok(size) if size != manifest.size_bytes => {
+            return Verification::Failed(format!(
+                "Size is {} bytes, manifest says {}",
+                size, manifest.size_bytes
+            ))
+        }
+        Ok(_) => {}
+        Err(e) => return Verification::Failed(format!("{:#}", e)),
+    }
+    let checksum_ok = match manifest::verify_checksum(path) {
+        Ok(Some(true)) => true,
+        Ok(Some(false)) => return Verification::Failed("SHA-256 checksum mismatch".to_string()),
+        Ok(None) => false,
+        Err(e) => return Verification::Failed(format!("{:#}", e)),
+    };
+    let Some(expected) = &manifest.content_hash else {
+        if checksum_ok {
+            return Verification::Passed;
+        }
+        return Verification::Unverified("Size OK, no content hash recorded".to_string());
+    };
+    if path.is_dir() {
+        return Verification::Passed;
+    }
+    match content_hash(path) {
+        Ok(actual) if &actual == expected => Verification::Passed,
+        Ok(_) => Verification::Failed("Content hash mismatch".to_string()),
+        Err(e) => Verification::Failed(format!("{:#}", e)),
+    }
+}
+
+/// Connects to `db` the way a backup would (credentials resolved, through
+/// `backup_host` if set) and runs `SELECT 1`.
+fn test_connection(db: &DatabaseConfig) -> Result<()> {
+    let source = db.backup_source()?;
+    let database = match &db.database_pattern {
+        Some(_) => client::maintenance_database(&db.db_type),
+        None => &source.connection.database,
+    };
+    client::check_connection(&db.db_type, &source.connection, database)
+}
+
+fn command_test(target_name: Option<String>) -> Result<()> {
+    let config = load_config()?;
+    let databases: Vec<&DatabaseConfig> = match &target_name {
+        Some(query) => vec![&config.databases[find_db_index(query, &config.databases)?]],
+        None => config.databases.iter().collect(),
+    };
+    if databases.is_empty() {
+        println!("No databases configured.");
+        return Ok(());
+    }
+
+    let mut table = new_table();
+    table.set_header(vec!["Config", "Server", "Result"]);
+    let mut failed = 0;
+    for db in databases {
+        let server = match db.db_type {
+            DbType::SQLite => db.connection.database.clone(),
+            _ => format!(
+                "{}:{}",
+                db.backup_host.as_deref().unwrap_or(&db.connection.host),
+                db.backup_port.unwrap_or(db.connection.port)
+            ),
+        };
+        let result = match test_connection(db) {
+            Ok(()) => Cell::new("OK").fg(Color::Green),
+            Err(e) => {
+                failed += 1;
+                Cell::new(format!("FAILED: {:#}", e)).fg(Color::Red)
+            }
+        };
+        table.add_row(vec![Cell::new(&db.name), Cell::new(server), result]);
+    }
+    println!("{table}");
+    if failed > 0 {
+        anyhow::bail!("{} database(s) could not be reached", failed);
+    }
+    Ok(())
+}
+
+fn command_verify(names: Vec<String>, all: bool) -> Result<()> {
+    let config = load_config()?;
+    let databases: Vec<&DatabaseConfig> = if all {
+        config.databases.iter().collect()
+    } else {
+        names
+            .iter()
+            .map(|query| Ok(&config.databases[find_db_index(query, &config.databases)?]))
+            .collect::<Result<_>>()?
+    };
+
+    let mut table = new_table();
+    table.set_header(vec!["Config", "Backup", "Result"]);
+    let (mut passed, mut failed, mut unverified) = (0, 0, 0);
+    for db in databases {
+        // Pattern configs keep each matched database's backups in a subdirectory
+        let dirs: Vec<DatabaseConfig> = match &db.database_pattern {
+            Some(_) => fs::read_dir(&db.output_dir)
+                .map(|entries| {
+                    entries
+                        .filter_map(|e| e.ok())
+                        .map(|e| e.path())
+                        .filter(|p| p.is_dir())
+                        .map(|dir| {
+                            let mut child = db.clone();
+                            child.output_dir = dir;
+                            child
+                        })
+                        .collect()
+                })
+                .unwrap_or_default(),
+            None => vec![db.clone()],
+        };
+        for dir in &dirs {
+            for backup in list_backups(dir) {
+                let name = backup.strip_prefix(&db.output_dir).unwrap_or(&backup);
+                let result = match verify_backup(&backup) {
+                    Verification::Passed => {
+                        passed += 1;
+                        Cell::new("OK").fg(Color::Green)
+                    }
+                    Verification::Failed(reason) => {
+                        failed += 1;
+                        Cell::new(format!("FAILED: {}", reason)).fg(Color::Red)
+                    }
+                    Verification::Unverified(reason) => {
+                        unverified += 1;
+                        Cell::new(reason).fg(Color::Yellow)
+                    }
+                };
+                table.add_row(vec![
+                    Cell::new(&db.name),
+                    Cell::new(name.to_string_lossy()),
+                    result,
+                ]);
+            }
+        }
+    }
+    println!("{table}");
+    println!(
+        "{} passed, {} failed, {} could not be verified",
+        passed, failed, unverified
+    );
+    if failed > 0 {
+        anyhow::bail!("{} backup(s) failed verification", failed);
+    }
+    Ok(())
+}
+
+/// Prints a backup's manifest and verifies its size and content hash against
+/// the file, failing if either doesn't match.
+fn command_inspect(file: PathBuf) -> Result<()> {
+    let manifest = Manifest::read(&file)
+        .with_context(|| format!("{:?} has no readable manifest; was it made by dbr?", file))?;
+
+    let mut header = Vec::new();
+    if !file.is_dir() {
+        split::open_raw(&file)?.take(64).read_to_end(&mut header)?;
+    }
+    let format = match client::BackupFormat::detect(&header) {
+        _ if file.is_dir() => "Directory (mydumper)",
+        client::BackupFormat::Plain => "Plain SQL",
+        client::BackupFormat::Gzip => "Gzip",
+        client::BackupFormat::Zstd => "Zstandard",
+        client::BackupFormat::Age => "Encrypted (age)",
+    };
+
+    let ok = |passed: bool| {
+        if passed {
+            Cell::new("OK").fg(Color::Green)
+        } else {
+            Cell::new("MISMATCH").fg(Color::Red)
+        }
+    };
+
+    let size = split::total_size(&file)?;
+    let size_ok = size == manifest.size_bytes;
+    let hash_ok = manifest
+        .content_hash
+        .as_ref()
+        .map(|expected| content_hash(&file).map(|actual| &actual == expected));
+
+    let mut table = new_table();
+    table.set_header(vec!["Field", "Value", "Check"]);
+    table.add_row(vec![Cell::new("Config"), Cell::new(&manifest.config_name)]);
+    table.add_row(vec![Cell::new("Database"), Cell::new(&manifest.database)]);
+    table.add_row(vec![
+        Cell::new("Type"),
+        Cell::new(manifest.db_type.to_string()),
+    ]);
+    table.add_row(vec![
+        Cell::new("Created"),
+        Cell::new(
+            manifest
+                .created_at
+                .format("%Y-%m-%d %H:%M:%S %z")
+                .to_string(),
+        ),
+    ]);
+    table.add_row(vec![
+        Cell::new("Size"),
+        Cell::new(format!(
+            "{} ({} bytes)",
+            format_bytes(manifest.size_bytes),
+            manifest.size_bytes
+        )),
+        if size_ok {
+            ok(true)
+        } else {
+            Cell::new(format!("MISMATCH ({} bytes on disk)", size)).fg(Color::Red)
+        },
+    ]);
+    table.add_row(vec![
+        Cell::new("Compression"),
+        Cell::new(manifest.compression.to_string()),
+    ]);
+    table.add_row(vec![Cell::new("Format"), Cell::new(format)]);
+    let hash_check = match &hash_ok {
+        Some(Ok(passed)) => ok(*passed),
+        Some(Err(e)) => Cell::new(format!("Not checked: {:#}", e)).fg(Color::Yellow),
+        None => Cell::new("-"),
+    };
+    table.add_row(vec![
+        Cell::new("Content Hash"),
+        Cell::new(manifest.content_hash.as_deref().unwrap_or("Not recorded")),
+        hash_check,
+    ]);
+    if let Some(position) = &manifest.binlog_position {
+        table.add_row(vec![
+            Cell::new("Binlog Position"),
+            Cell::new(position.to_string()),
+        ]);
+    }
+    if let Some(check) = &manifest.restore_check {
+        table.add_row(vec![
+            Cell::new("Restore Check"),
+            Cell::new(format!(
+                "{} at {}",
+                if check.passed { "Passed" } else { "Failed" },
+                check.checked_at.format("%Y-%m-%d %H:%M:%S")
+            )),
+        ]);
+    }
+    println!("{table}");
+
+    if !size_ok || matches!(hash_ok, Some(Ok(false))) {
+        anyhow::bail!("{:?} does not match its manifest", file);
+    }
+    Ok(())
+}
+
+/// A repair `doctor --fix` can make.
+enum Fix {
+    /// Rewrite the config in the current schema version
+    Migrate,
+    CreateDir(PathBuf),
+    ClearSchedule(String),
+    RemoveFile(PathBuf),
+}
+
+impl std::fmt::Display for Fix {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Fix::Migrate => write!(f, "save the config in the current format"),
+            Fix::CreateDir(dir) => write!(f, "create {:?}", dir),
+            Fix::ClearSchedule(name) => write!(f, "clear the schedule of {}", name),
+            Fix::RemoveFile(path) => write!(f, "delete {:?}", path),
+        }
+    }
+}
+
+/// `--config-check`: parses and validates the config without creating or
+/// writing anything, so CI can vet a rendered config before deploying it.
+/// Reports every invalid field and schedule, then fails if there were any;
+/// pending migrations are listed but not applied.
+fn command_config_check() -> Result<()> {
+    let config_path = locate_config_path()?;
+    if !config_path.exists() {
+        anyhow::bail!("Config file {:?} does not exist", config_path);
+    }
+    let (config, migrations) = read_config(&config_path)
+        .with_context(|| format!("Failed to parse config {:?}", config_path))?;
+
+    let mut problems = Vec::new();
+    if let Some(value) = &config.log_level {
+        if configured_log_level(&config).is_none() {
+            problems.push(format!("invalid log_level '{}'", value));
+        }
+    }
+    for db in &config.databases {
+        if let Err(e) = db.validate() {
+            problems.push(format!("{}: {:#}", db.name, e));
+        }
+        if let Some(schedule) = &db.schedule {
+            if let Err(e) = Schedule::from_str(schedule) {
+                problems.push(format!(
+                    "{}: invalid schedule '{}': {}",
+                    db.name, schedule, e
+                ));
+            }
+        }
+    }
+    for problem in &problems {
+        eprintln!("{}", problem);
+    }
+    if !problems.is_empty() {
+        anyhow::bail!("{} problem(s) found in {:?}", problems.len(), config_path);
+    }
+
+    for step in &migrations {
+        println!("Will be migrated when next saved: {}", step);
+    }
+    println!(
+        "{:?} is valid ({} database(s))",
+        config_path,
+        config.databases.len()
+    );
+    Ok(())
+}
+
+/// Reports problems with the config and backup directories, and with `fix`
+/// repairs the safe ones after confirmation. Fails if any problem remains.
+fn command_doctor(fix: bool, yes: bool) -> Result<()> {
+    let config_path = get_config_path()?;
+    if !config_path.exists() {
+        println!(
+            "No config at {:?}; run `dbr init` or `dbr add` to create one.",
+            config_path
+        );
+        return Ok(());
+    }
+    let (mut config, migrations) = read_config(&config_path)?;
+
+    let mut findings: Vec<(String, Option<Fix>)> = Vec::new();
+    for step in migrations {
+        findings.push((
+            format!("Config needs migrating ({})", step),
+            Some(Fix::Migrate),
+        ));
+    }
+
+    // Leftover temp files are only stale if no backup is running right now
+    let busy = config.databases.iter().any(config_is_busy);
+    for db in &config.databases {
+        if let Err(e) = db.validate() {
+            findings.push((format!("{}: {:#}", db.name, e), None));
+        }
+        if let Some(schedule) = &db.schedule {
+            if let Err(e) = Schedule::from_str(schedule) {
+                findings.push((
+                    format!("{}: invalid schedule '{}': {}", db.name, schedule, e),
+                    Some(Fix::ClearSchedule(db.name.clone())),
+                ));
+            }
+        }
+        if !db.output_dir.exists() {
+            findings.push((
+                format!(
+                    "{}: output directory {:?} does not exist",
+                    db.name, db.output_dir
+                ),
+                Some(Fix::CreateDir(db.output_dir.clone())),
+            ));
+            continue;
+        }
+
+        let unrelated = unrelated_files(&db.output_dir).len();
+        if !db.allow_shared_output_dir && unrelated > UNRELATED_FILES_LIMIT {
+            findings.push((
+                format!(
+                    "{}: output directory {:?} contains {} files that aren't backups; backups there are refused until it gets a dedicated directory",
+                    db.name, db.output_dir, unrelated
+                ),
+                None,
+            ));
+        }
+
+        let prefix = format!("{}_", db.name);
+        for entry in fs::read_dir(&db.output_dir)?.filter_map(|e| e.ok()) {
+            let path = entry.path();
+            let name = entry.file_name().to_string_lossy().to_string();
+            if !name.starts_with(&prefix) || !path.is_file() {
+                continue;
+            }
+            if split::logical_name(&name).ends_with(".tmp") && !busy {
+                findings.push((
+                    format!(
+                        "{}: incomplete backup {:?} left by an interrupted run",
+                        db.name, path
+                    ),
+                    Some(Fix::RemoveFile(path)),
+                ));
+            } else if let Some(backup) = name.strip_suffix(".json") {
+                if is_backup_file_name(backup) && !split::exists(&db.output_dir.join(backup)) {
+                    findings.push((
+                        format!("{}: manifest {:?} has no backup", db.name, path),
+                        Some(Fix::RemoveFile(path)),
+                    ));
+                }
+            } else if let Some(backup) = name.strip_suffix(manifest::CHECKSUM_SUFFIX) {
+                if is_backup_file_name(backup) && !split::exists(&db.output_dir.join(backup)) {
+                    findings.push((
+                        format!("{}: checksum {:?} has no backup", db.name, path),
+                        Some(Fix::RemoveFile(path)),
+                    ));
+                }
+            } else if let Some(stem) = name.strip_suffix(manifest::SERVER_CONFIG_SUFFIX) {
+                let has_backup = manifest::BACKUP_EXTENSIONS
+                    .iter()
+                    .any(|ext| split::exists(&db.output_dir.join(format!("{}{}", stem, ext))));
+                if !has_backup {
+                    findings.push((
+                        format!("{}: server configuration {:?} has no backup", db.name, path),
+                        Some(Fix::RemoveFile(path)),
+                    ));
+                }
+            }
+        }
+    }
+
+    if findings.is_empty() {
+        println!("No problems found.");
+        return Ok(());
+    }
+
+    let mut remaining = 0;
+    let mut config_changed = false;
+    for (problem, repair) in findings {
+        println!("✗ {}", problem);
+        let Some(repair) = repair.filter(|_| fix) else {
+            remaining += 1;
+            continue;
+        };
+        let confirmed = yes
+            || Confirm::with_theme(&ColorfulTheme::default())
+                .with_prompt(format!("Fix: {}?", repair))
+                .default(false)
+                .interact()?;
+        if !confirmed {
+            remaining += 1;
+            continue;
+        }
+        match &repair {
+            Fix::Migrate => config_changed = true,
+            Fix::CreateDir(dir) => fs::create_dir_all(dir)?,
+            Fix::ClearSchedule(name) => {
+                if let Some(db) = config.databases.iter_mut().find(|db| &db.name == name) {
+                    db.schedule = None;
+                    config_changed = true;
+                }
+            }
+            Fix::RemoveFile(path) => fs::remove_file(path)?,
+        }
+        info!("doctor: {}", repair);
+    }
+    if config_changed {
+        save_config(&config)?;
+    }
+
+    if remaining > 0 {
+        if fix {
+            anyhow::bail!("{} problem(s) left unfixed", remaining);
+        }
+        anyhow::bail!(
+            "{} problem(s) found; `dbr doctor --fix` can repair some of them",
+            remaining
+        );
+    }
+    Ok(())
+}
+
+/// Whether another process is backing up `db` right now.
+fn config_is_busy(db: &DatabaseConfig) -> bool {
+    let Ok(path) = get_lock_path(&db.name) else {
+        return false;
+    };
+    let Ok(file) = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(path)
+    else {
+        return false;
+    };
+    matches!(file.try_lock(), Err(fs::TryLockError::WouldBlock))
+}
+
+/// Prints the last `lines` lines of the combined log, or of one database's
+/// log, optionally following it as it grows.
+async fn command_logs(name: Option<String>, lines: usize, follow: bool) -> Result<()> {
+    let config_dir = ProjectDirs::from("com", "db-shield", "db-backup-rs")
+        .map(|dirs| dirs.config_dir().to_path_buf())
+        .context("Could not determine config directory")?;
+    let path = match name {
+        Some(query) => {
+            let config = load_config()?;
+            let db = &config.databases[find_db_index(&query, &config.databases)?];
+            if config.log_files == LogFiles::Combined {
+                anyhow::bail!(
+                    "Per-database logs are off; set log_files to \"Both\" or \"PerDatabase\""
+                );
+            }
+            logging::database_log_path(&config_dir.join("logs"), &db.name)
+        }
+        None => config_dir.join("backup.log"),
+    };
+
+    let mut file = match fs::File::open(&path) {
+        Ok(file) => file,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound && !follow => {
+            println!("No log yet at {:?}", path);
+            return Ok(());
+        }
+        Err(e) => return Err(e).with_context(|| format!("Failed to open {:?}", path)),
+    };
+    let mut content = String::new();
+    file.read_to_string(&mut content)?;
+    let all: Vec<&str> = content.lines().collect();
+    for line in &all[all.len().saturating_sub(lines)..] {
+        println!("{}", line);
+    }
+
+    if follow {
+        let mut buf = String::new();
+        loop {
+            tokio::select! {
+                _ = shutdown_signal() => return Ok(()),
+                _ = sleep(Duration::from_millis(500)) => {}
+            }
+            buf.clear();
+            file.read_to_string(&mut buf)?;
+            print!("{}", buf);
+            std::io::stdout().flush().ok();
+        }
+    }
+    Ok(())
+}
+
+/// Creates a table with the standard preset and the `--color` choice applied.
+fn new_table() -> Table {
+    let mut table = Table::new();
+    table.load_preset(comfy_table::presets::UTF8_FULL);
+    match COLOR_MODE.get() {
+        Some(ColorMode::Always) => {
+            table.enforce_styling();
+        }
+        Some(ColorMode::Never) => {
+            table.force_no_tty();
+        }
+        _ => {}
+    }
+    table
+}
+
+fn format_bytes(bytes: u64) -> String {
+    const UNITS: [&str; 5] = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = bytes as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < UNITS.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", bytes, UNITS[0])
+    } else {
+        format!("{:.1} {}", size, UNITS[unit])
+    }
+}
+
+/// A duration as e.g. "8.2s", "2m14s" or "1h05m".
+fn format_elapsed(elapsed: Duration) -> String {
+    let secs = elapsed.as_secs();
+    if secs < 60 {
+        format!("{:.1}s", elapsed.as_secs_f64())
+    } else if secs < 3600 {
+        format!("{}m{:02}s", secs / 60, secs % 60)
+    } else {
+        format!("{}h{:02}m", secs / 3600, secs % 3600 / 60)
+    }
+}
+
+async fn command_restore(args: RestoreArgs) -> Result<()> {
+    let config = load_config()?;
+    let idx = match &args.name {
+        Some(query) => find_db_index(query, &config.databases)?,
+        None => {
+            if config.databases.is_empty() {
+                anyhow::bail!("No databases configured");
+            }
+            let items: Vec<String> = config
+                .databases
+                .iter()
+                .map(|db| format!("{} ({})", db.name, db.db_type))
+                .collect();
+            Select::with_theme(&ColorfulTheme::default())
+                .with_prompt("Select database to restore")
+                .items(&items)
+                .default(0)
+                .interact()?
+        }
+    };
+    let db = &config.databases[idx];
+
+    let file = match args.file {
+        Some(file) => file,
+        None if args.latest => {
+            get_last_backup(db).with_context(|| format!("No backups found for '{}'", db.name))?
+        }
+        None => select_backup(db)?,
+    };
+
+    if !split::exists(&file) {
+        anyhow::bail!("Backup file not found: {:?}", file);
+    }
+    // Fail on unsupported or encrypted formats before asking to overwrite anything
+    if !file.is_dir() {
+        client::open_backup(&file)?;
+    }
+
+    let mut target = db.resolved()?.connection;
+    if let Some(host) = args.target_host {
+        target.host = host;
+    }
+    if let Some(database) = args.target_database {
+        target.database = database;
+    }
+    if let Some(user) = args.target_user {
+        target.user = user;
+        target.password = Password::with_theme(&ColorfulTheme::default())
+            .with_prompt(format!("Password for {} (optional)", target.user))
+            .allow_empty_password(true)
+            .interact()
+            .ok()
+            .filter(|p| !p.is_empty());
+    }
+
+    let destination = if db.db_type == DbType::SQLite {
+        warn!(
+            "Restoring will REPLACE the database file {}",
+            target.database
+        );
+        target.database.clone()
+    } else {
+        warn!(
+            "Restoring will OVERWRITE data in database '{}' on {}:{}",
+            target.database, target.host, target.port
+        );
+        format!("'{}' on {}", target.database, target.host)
+    };
+    if !Confirm::with_theme(&ColorfulTheme::default())
+        .with_prompt(format!("Restore {:?} into {}?", file, destination))
+        .default(false)
+        .interact()?
+    {
+        println!("Restore cancelled.");
+        return Ok(());
+    }
+
+    client::restore_file(&db.db_type, &target, &target.database, &file)?;
+    info!("Restored {:?} into {}", file, destination);
+    Ok(())
+}
+
+/// Asks which of `db`'s backups to restore, newest first.
+fn select_backup(db: &DatabaseConfig) -> Result<PathBuf> {
+    let mut backups = list_backups(db);
+    if backups.is_empty() {
+        anyhow::bail!("No backups found for '{}'", db.name);
+    }
+    backups.reverse();
+    let items: Vec<String> = backups
+        .iter()
+        .map(|path| {
+            let name = path
+                .file_name()
+                .map(|n| n.to_string_lossy().to_string())
+                .unwrap_or_default();
+            let size = split::total_size(path)
+                .map(format_bytes)
+                .unwrap_or_default();
+            format!("{} ({})", name, size)
+        })
+        .collect();
+    let idx = Select::with_theme(&ColorfulTheme::default())
+        .with_prompt("Select backup to restore")
+        .items(&items)
+        .default(0)
+        .interact()?;
+    Ok(backups.swap_remove(idx))
+}
+
+async fn command_delete(target_name: Option<String>) -> Result<()> {
+    let mut config = load_config()?;
+    if config.databases.is_empty() {
+        println!("No databases configured.");
+        return Ok(());
+    }
+
+    // A name on the command line is explicit enough when only destructive
+    // operations are to be confirmed; picking from the menu still asks
+    let skip_confirm = target_name.is_some()
+        && (config.confirm_destructive_only || CONFIRM_DESTRUCTIVE_ONLY.load(Ordering::Relaxed));
+    let selection_idx = if let Some(query) = target_name {
+        find_db_index(&query, &config.databases)?
+    } else {
+        // Explicitly add an "Exit" option
+        let mut options: Vec<String> = config
+            .databases
+            .iter()
+            .enumerate()
+            .map(|(i, db)| format!("{}. {} ({})", i + 1, db.name, db.db_type))
+            .collect();
+        options.push("Cancel / Exit".to_string());
+
+        let idx = Select::with_theme(&ColorfulTheme::default())
+            .with_prompt("Select configuration to delete")
+            .items(&options)
+            .default(0)
+            .interact()?;
+
+        // Check if the user selected default Cancel / Exit
+        if idx == options.len() - 1 {
+            println!("Deletion cancelled.");
+            return Ok(());
+        }
+        idx
+    };
+
+    if skip_confirm
+        || Confirm::with_theme(&ColorfulTheme::default())
+            .with_prompt(format!(
+                "Are you sure you want to delete '{}'?",
+                config.databases[selection_idx].name
+            ))
+            .interact()?
+    {
+        config.databases.remove(selection_idx);
+        save_config_unprompted(&config)?;
+        println!("Configuration deleted.");
+    } else {
+        println!("Deletion cancelled.");
+    }
+
+    Ok(())
+}
+
+async fn command_edit(target_name: Option<String>) -> Result<()> {
+    // Check if there are any configurations to edit
+    let mut config = load_config()?;
+    if config.databases.is_empty() {
+        println!("No databases configured.");
+        return Ok(());
+    }
+
+    let selection_idx = if let Some(query) = target_name {
+        find_db_index(&query, &config.databases)?
+    } else {
+        // Select which configuration to edit
+        let mut options: Vec<String> = config
+            .databases
+            .iter()
+            .enumerate()
+            .map(|(i, db)| format!("{}. {} ({})", i + 1, db.name, db.db_type))
+            .collect();
+        options.push("Cancel / Exit".to_string());
+
+        let idx = Select::with_theme(&ColorfulTheme::default())
+            .with_prompt("Select configuration to edit")
+            .items(&options)
+            .default(0)
+            .interact()?;
+
+        if idx == options.len() - 1 {
+            println!("Exiting edit mode.");
+            return Ok(());
+        }
+        idx
+    };
+
+    let db = &mut config.databases[selection_idx];
+
+    // Select which field to edit
+    let fields = vec![
+        "Name",
+        "Host",
+        "Port",
+        "User",
+        "Password",
+        "Database",
+        "Output Directory",
+        "Retention Count",
+        "Schedule",
+        "Description",
+        "Compression",
+        "Retention Days",
+        "Tables",
+        "Extra Dump Options",
+        "Exit Edit Mode",
+    ];
+
+    loop {
+        let field_selection = Select::with_theme(&ColorfulTheme::default())
+            .with_prompt(format!("Editing '{}'. Select field to change", db.name))
+            .items(&fields)
+            .default(fields.len() - 1) // Default to Exit
+            .interact()?;
+
+        match field_selection {
+            0 => {
+                // Name
+                db.name = Input::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Name")
+                    .default(db.name.clone())
+                    .interact_text()?;
+            }
+            1..=4 if db.db_type == DbType::SQLite => {
+                println!("{} is not used for SQLite.", fields[field_selection]);
+            }
+            1 => {
+                // Host
+                db.connection.host = Input::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Host")
+                    .default(db.connection.host.clone())
+                    .interact_text()?;
+            }
+            2 => {
+                // Port
+                db.connection.port = Input::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Port")
+                    .default(db.connection.port)
+                    .interact_text()?;
+            }
+            3 => {
+                // User
+                db.connection.user = Input::with_theme(&ColorfulTheme::default())
+                    .with_prompt("User")
+                    .default(db.connection.user.clone())
+                    .interact_text()?;
+            }
+            4 => {
+                // Password
+                let new_pass = Password::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Password (leave empty to keep unchanged, type 'clear' to remove)")
+                    .allow_empty_password(true)
+                    .interact()?;
+
+                if new_pass == "clear" {
+                    db.connection.password = None;
+                } else if !new_pass.is_empty() {
+                    db.connection.password = Some(new_pass);
+                }
+            }
+            5 => {
+                // Database
+                let prompt = if db.db_type == DbType::SQLite {
+                    "Database file path"
+                } else {
+                    "Database"
+                };
+                db.connection.database = Input::with_theme(&ColorfulTheme::default())
+                    .with_prompt(prompt)
+                    .default(db.connection.database.clone())
+                    .interact_text()?;
+            }
+            6 => {
+                // Output Dir
+                let path_str = Input::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Output Directory")
+                    .default(db.output_dir.to_string_lossy().to_string())
+                    .interact_text()?;
+                db.output_dir = PathBuf::from(path_str);
+            }
+            7 => {
+                // Retention
+                db.retention_count = Input::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Retention Count")
+                    .default(db.retention_count)
+                    .interact_text()?;
+            }
+            8 => {
+                // Schedule
+                println!(
+                    "Current Schedule: {}",
+                    db.schedule.clone().unwrap_or_else(|| "None".to_string())
+                );
+                let new_schedule = get_schedule_input()?;
+                db.schedule = Some(new_schedule);
+            }
+            9 => {
+                // Description (empty removes it)
+                let description: String = Input::with_theme(&ColorfulTheme::default())
+                    .with_prompt("Description")
+                    .default(db.description.clone().unwrap_or_default())
+                    .allow_empty(true)
+                    .interact_text()?;
+                db.description = Some(description.trim().to_string()).filter(|d| !d.is_empty());
+            }
+            10 => db.compression = prompt_compression(db.compression)?,
+            11 => prompt_retention_days(db)?,
+            12 => prompt_tables(db)?,
+            13 => prompt_extra_args(db)?,
+            14 => break, // Exit
+            _ => unreachable!(),
+        }
+    }
+
+    save_config(&config)?;
+    println!("Configuration updated successfully!");
+    Ok(())
+}
+
+async fn command_run(args: RunArgs) -> Result<()> {
+    let config = load_config()?;
+    if config.databases.is_empty() {
+        warn!("No databases configured. Run `add` command first.");
+        return Ok(());
+    }
+
+    let dump_args = match &args.dump_args {
+        Some(line) => shell_words::split(line).context("Invalid --dump-args")?,
+        None => Vec::new(),
+    };
+
+    if args.stdout {
+        if args.names.len() != 1 {
+            anyhow::bail!("--stdout requires exactly one database name");
+        }
+        let idx = find_db_index(&args.names[0], &config.databases)?;
+        return dump_to_stdout(&config.databases[idx], &dump_args);
+    }
+
+    let candidates = selectable_databases(&config, args.include_disabled);
+    let databases: Vec<DatabaseConfig> = if !args.names.is_empty() {
+        let mut chosen: Vec<&DatabaseConfig> = Vec::new();
+        for query in &args.names {
+            let db = &config.databases[find_db_index(query, &config.databases)?];
+            if !candidates.iter().any(|c| c.name == db.name) {
+                warn!(
+                    "Skipping disabled database {} (use --include-disabled to back it up)",
+                    db.name
+                );
+            } else if !chosen.iter().any(|c| c.name == db.name) {
+                chosen.push(db);
+            }
+        }
+        chosen.into_iter().cloned().collect()
+    } else if args.retry_failed {
+        let failed = load_state()?.failed_runs;
+        let retry: Vec<DatabaseConfig> = candidates
+            .into_iter()
+            .filter(|db| failed.contains(&db.name))
+            .cloned()
+            .collect();
+        if retry.is_empty() {
+            println!("No failed backups to retry.");
+            return Ok(());
+        }
+        info!(
+            "Retrying {} failed database(s): {}",
+            retry.len(),
+            retry
+                .iter()
+                .map(|db| db.name.as_str())
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        retry
+    } else if !args.all && std::io::stdin().is_terminal() && std::io::stderr().is_terminal() {
+        if candidates.is_empty() {
+            println!("All databases are disabled (use --include-disabled to back them up).");
+            return Ok(());
+        }
+        let items: Vec<String> = candidates
+            .iter()
+            .map(|db| format!("{} ({})", db.name, db.db_type))
+            .collect();
+        let defaults: Vec<bool> = candidates.iter().map(|db| db.enabled).collect();
+
+        let chosen = MultiSelect::with_theme(&ColorfulTheme::default())
+            .with_prompt("Select databases to back up (space to toggle, enter to confirm)")
+            .items(&items)
+            .defaults(&defaults)
+            .interact()?;
+        if chosen.is_empty() {
+            println!("No databases selected.");
+            return Ok(());
+        }
+        chosen.into_iter().map(|i| candidates[i].clone()).collect()
+    } else {
+        candidates.into_iter().cloned().collect()
+    };
+
+    let jobs = usize::from(args.jobs);
+    let opts = BackupOptions {
+        no_rotate: args.no_rotate,
+        manual: args.manual,
+        force: args.force,
+        dump_args,
+        // Progress lines of concurrent dumps would overwrite each other
+        progress: std::io::stderr().is_terminal() && (jobs == 1 || databases.len() == 1),
+        limit_rate: args.limit_rate,
+        label: args.label,
+        preflight: false,
+    };
+    let started = std::time::Instant::now();
+    let (mut succeeded, mut failed, mut skipped, mut written) = (0, 0, 0, 0);
+    let mut outcomes: Vec<(String, bool)> = Vec::new();
+    let mut pending = databases.into_iter();
+    let mut running = tokio::task::JoinSet::new();
+    loop {
+        // Keep up to `jobs` backups going; a failure doesn't stop the others
+        while running.len() < jobs {
+            let Some(mut db) = pending.next() else {
+                break;
+            };
+            if let Some(dir) = &args.output_dir {
+                db.output_dir = dir.clone();
+            }
+            let opts = opts.clone();
+            // Dumps block their thread while waiting on the dump tool, so give
+            // each backup its own rather than sharing the runtime's workers
+            let runtime = tokio::runtime::Handle::current();
+            running.spawn_blocking(move || {
+                let result = runtime.block_on(perform_backup(&db, &opts));
+                (db.name, result)
+            });
+        }
+        let Some(joined) = running.join_next().await else {
+            break;
+        };
+        let (name, result) = joined.context("Backup task panicked")?;
+        notify_result(&config, &name, &result);
+        outcomes.push((name, result.is_ok()));
+        match result {
+            Ok(BackupOutcome::Created { bytes }) => {
+                succeeded += 1;
+                written += bytes;
+            }
+            Ok(
+                BackupOutcome::Deduplicated | BackupOutcome::TooRecent | BackupOutcome::NoMatches,
+            ) => skipped += 1,
+            Err(_) => failed += 1,
+        }
+    }
+    if let Err(e) = record_failed_runs(&outcomes) {
+        warn!("Failed to record which backups failed: {}", e);
+    }
+    info!(
+        "{} succeeded, {} failed, {} skipped (deduplicated or too recent), {} written in {}",
+        succeeded,
+        failed,
+        skipped,
+        format_bytes(written),
+        format_elapsed(started.elapsed())
+    );
+    if failed > 0 {
+        anyhow::bail!("{} backup(s) failed", failed);
+    }
+    Ok(())
+}
+
+/// Remembers which configs failed in this run for `run --retry-failed`,
+/// forgetting earlier failures of the ones that have now succeeded.
+fn record_failed_runs(outcomes: &[(String, bool)]) -> Result<()> {
+    update_state(|state| {
+        for (name, ok) in outcomes {
+            if *ok {
+                state.failed_runs.remove(name);
+            } else {
+                state.failed_runs.insert(name.clone());
+            }
+        }
+    })
+}
+
+/// A friendlier `run` for backing up a single database by hand.
+async fn command_now(name: Option<String>) -> Result<()> {
+    let config = load_config()?;
+    let candidates = selectable_databases(&config, false);
+    if candidates.is_empty() {
+        println!("No enabled databases configured. Run `dbr add` first.");
+        return Ok(());
+    }
+
+    let db = match name {
+        Some(query) => {
+            let db = &config.databases[find_db_index(&query, &config.databases)?];
+            if !db.enabled {
+                anyhow::bail!(
+                    "{} is disabled; enable it with `dbr start {}` first",
+                    db.name,
+                    db.name
+                );
+            }
+            db
+        }
+        None => {
+            let items: Vec<String> = candidates
+                .iter()
+                .map(|db| format!("{} ({})", db.name, db.db_type))
+                .collect();
+            let idx = Select::with_theme(&ColorfulTheme::default())
+                .with_prompt("Select database to back up")
+                .items(&items)
+                .default(0)
+                .interact()?;
+            candidates[idx]
+        }
+    };
+
+    let opts = BackupOptions {
+        progress: std::io::stderr().is_terminal(),
+        ..Default::default()
+    };
+    let started = std::time::Instant::now();
+    let outcome = perform_backup(db, &opts).await;
+    let elapsed = format_elapsed(started.elapsed());
+    match outcome {
+        Ok(BackupOutcome::Created { bytes }) => println!(
+            "✔ Backed up {}: {} in {}",
+            db.name,
+            format_bytes(bytes),
+            elapsed
+        ),
+        Ok(BackupOutcome::Deduplicated) => println!(
+            "✔ {} is unchanged since its last backup; nothing new was kept (use `dbr run {} --force` to keep it anyway)",
+            db.name, db.name
+        ),
+        Ok(BackupOutcome::TooRecent) => println!(
+            "✔ {} was backed up less than min_interval_seconds ago; skipped",
+            db.name
+        ),
+        Ok(BackupOutcome::NoMatches) => println!(
+            "No databases match the database_pattern of {}; nothing to back up",
+            db.name
+        ),
+        Err(e) => anyhow::bail!("Backup of {} failed after {}: {}", db.name, elapsed, e),
+    }
+    Ok(())
+}
+
+/// The configs a backup run may choose from. Both `run` and the daemon go
+/// through this, so a config disabled with `stop` is left alone by both;
+/// only `run --include-disabled` reaches past it.
+fn selectable_databases(config: &AppConfig, include_disabled: bool) -> Vec<&DatabaseConfig> {
+    config
+        .databases
+        .iter()
+        .filter(|db| db.enabled || include_disabled)
+        .collect()
+}
+
+/// Result of the most recent daemon-triggered backup, shown by `--watch`.
+struct LastRun {
+    succeeded: bool,
+    finished_at: chrono::DateTime<Local>,
+    duration: Duration,
+}
+
+/// A scheduled backup the daemon has started but not yet collected.
+struct ScheduledRun {
+    id: u64,
+    /// Dropping or sending stops the backup, killing its dump
+    cancel: tokio::sync::oneshot::Sender<()>,
+}
+
+/// What a daemon backup task hands back when it ends.
+struct FinishedRun {
+    name: String,
+    id: u64,
+    due_time: chrono::DateTime<Local>,
+    /// `None` if it was stopped before finishing
+    finished: Option<Option<ScheduledResult>>,
+}
+
+async fn command_daemon(watch: bool, catch_up: bool) -> Result<()> {
+    info!("Starting daemon mode...");
+
+    let pid_path = get_pid_path()?;
+    if let Some(pid) = fs::read_to_string(&pid_path)
+        .ok()
+        .and_then(|p| p.trim().parse::<u32>().ok())
+    {
+        if pid != std::process::id() && process_is_running(pid) {
+            anyhow::bail!("Daemon is already running (PID {})", pid);
+        }
+    }
+    fs::write(&pid_path, std::process::id().to_string())?;
+
+    #[cfg(unix)]
+    tokio::spawn(reload_log_level_on_hangup());
+
+    let result = tokio::select! {
+        result = daemon_loop(watch, catch_up) => result,
+        _ = shutdown_signal() => {
+            info!("Shutting down daemon...");
+            Ok(())
+        }
+    };
+
+    fs::remove_file(&pid_path).ok();
+    fs::remove_file(get_daemon_status_path()?).ok();
+    result
+}
+
+/// `log_level` from the config, if it is set and valid.
+fn configured_log_level(config: &AppConfig) -> Option<LevelFilter> {
+    config.log_level.as_deref()?.parse().ok()
+}
+
+/// Re-reads the log level whenever the daemon gets SIGHUP, so debug output
+/// can be turned on during an incident without a restart.
+#[cfg(unix)]
+async fn reload_log_level_on_hangup() {
+    use tokio::signal::unix::{signal, SignalKind};
+    let Ok(mut hangup) = signal(SignalKind::hangup()) else {
+        warn!("Could not listen for SIGHUP; log level changes need a restart");
+        return;
+    };
+    while hangup.recv().await.is_some() {
+        let config = locate_config_path().and_then(|path| read_config(&path));
+        let level = match config {
+            Ok((config, _)) => match (&config.log_level, configured_log_level(&config)) {
+                (Some(value), None) => {
+                    warn!(
+                        "SIGHUP: invalid log_level '{}'; keeping {}",
+                        value,
+                        log::max_level()
+                    );
+                    continue;
+                }
+                (_, Some(level)) => level,
+                (None, None) => ENV_LOG_LEVEL.get().copied().unwrap_or(LevelFilter::Info),
+            },
+            Err(e) => {
+                warn!(
+                    "SIGHUP: could not read config: {:#}; keeping {}",
+                    e,
+                    log::max_level()
+                );
+                continue;
+            }
+        };
+        log::set_max_level(level);
+        info!("SIGHUP: log level set to {}", level);
+    }
+}
+
+/// Resolves on Ctrl+C, or SIGTERM on Unix (what service managers send).
+async fn shutdown_signal() {
+    #[cfg(unix)]
+    {
+        use tokio::signal::unix::{signal, SignalKind};
+        match signal(SignalKind::terminate()) {
+            Ok(mut term) => {
+                tokio::select! {
+                    _ = tokio::signal::ctrl_c() => {}
+                    _ = term.recv() => {}
+                }
+            }
+            Err(_) => {
+                tokio::signal::ctrl_c().await.ok();
+            }
+        }
+    }
+    #[cfg(not(unix))]
+    {
+        tokio::signal::ctrl_c().await.ok();
+    }
+}
+
+async fn daemon_loop(watch: bool, catch_up: bool) -> Result<()> {
+    let mut status = DaemonStatus {
+        pid: std::process::id(),
+        started_at: Local::now(),
+        updated_at: Local::now(),
+        backups: 0,
+        failures: 0,
+    };
+    save_daemon_status(&status)?;
+
+    // The latest fire time handled per config, also saved in the state file
+    let mut last_run_times: std::collections::HashMap<String, chrono::DateTime<Local>> =
+        std::collections::HashMap::new();
+    if catch_up {
+        // Pick up where the previous daemon (or `--oneshot`) left off
+        for (name, db_state) in load_state()?.databases {
+            if let Some(last) = db_state.last_scheduled_run {
+                last_run_times.insert(name, last);
+            }
+        }
+    }
+    let mut last_results: std::collections::HashMap<String, LastRun> =
+        std::collections::HashMap::new();
+    // Fire times held back by a blackout window, run once it ends
+    let mut deferred: std::collections::HashMap<String, chrono::DateTime<Local>> =
+        std::collections::HashMap::new();
+    let mut last_config: Option<String> = None;
+    // Due backups run side by side, so a slow dump doesn't hold up other
+    // configs' schedules. The latest run of each config is tracked for
+    // `overlap_policy`.
+    let mut running: tokio::task::JoinSet<FinishedRun> = tokio::task::JoinSet::new();
+    let mut in_progress: std::collections::HashMap<String, ScheduledRun> =
+        std::collections::HashMap::new();
+    let mut next_run_id = 0u64;
+
+    loop {
+        sleep(Duration::from_secs(10)).await;
+
+        while let Some(joined) = running.try_join_next() {
+            let FinishedRun {
+                name,
+                id,
+                due_time,
+                finished,
+            } = joined.context("Backup task panicked")?;
+            if in_progress.get(&name).is_some_and(|run| run.id == id) {
+                in_progress.remove(&name);
+            }
+            // Stopped for a newer run (`KillPrevious`), which records its own
+            let Some(finished) = finished else {
+                continue;
+            };
+            if let Err(e) = record_scheduled_run(&name, due_time) {
+                warn!("Failed to save the last scheduled run of {}: {:#}", name, e);
+            }
+            let Some((result, duration)) = finished else {
+                continue;
+            };
+            status.backups += 1;
+            if result.is_err() {
+                status.failures += 1;
+            }
+            status.updated_at = Local::now();
+            if let Err(e) = save_daemon_status(&status) {
+                warn!("Failed to update daemon status: {}", e);
+            }
+            last_results.insert(
+                name,
+                LastRun {
+                    succeeded: result.is_ok(),
+                    finished_at: Local::now(),
+                    duration,
+                },
+            );
+        }
+
+        let now = Local::now();
+
+        let config = match load_config() {
+            Ok(c) => c,
+            Err(e) => {
+                error!("Config error: {}", e);
+                continue;
+            }
+        };
+        let serialized = serde_json::to_string(&config).ok();
+        if last_config.is_some() && last_config != serialized {
+            events::emit(Event::ConfigReloaded {
+                databases: config.databases.len(),
+            });
+        }
+        last_config = serialized;
+
+        if watch {
+            render_dashboard(&config.databases, &last_results);
+        }
+
+        for db in selectable_databases(&config, false) {
+            if let Some(schedule_str) = &db.schedule {
+                if let Ok(schedule) = Schedule::from_str(schedule_str) {
+                    let jitter = db.jitter_seconds.or(config.jitter_seconds).unwrap_or(0);
+                    // Widen the window by the jitter so a delayed fire time is still found
+                    let window_start = now - chrono::Duration::seconds(61 + jitter as i64);
+                    let search_start = match last_run_times.get(&db.name) {
+                        // Only the latest fire time found is run, however many were missed
+                        Some(last) if catch_up => (*last).min(window_start),
+                        _ => window_start,
+                    };
+                    if catch_up && !last_run_times.contains_key(&db.name) {
+                        // Start counting from here so a fire time missed later is caught up
+                        mark_handled(&mut last_run_times, &db.name, window_start);
+                    }
+                    let due = schedule
+                        .after(&search_start)
+                        .take_while(|t| *t <= now)
+                        .filter(|t| *t + jitter_offset(&db.name, t, jitter) <= now)
+                        .last();
+                    let mut run_due = due.filter(|due_time| {
+                        last_run_times
+                            .get(&db.name)
+                            .is_none_or(|last| last < due_time)
+                    });
+
+                    if let Some(due_time) = run_due.filter(|t| db.in_blackout(t)) {
+                        mark_handled(&mut last_run_times, &db.name, due_time);
+                        run_due = None;
+                        match db.blackout_policy {
+                            BlackoutPolicy::Skip => info!(
+                                "Skipping backup for {} due at {}: inside blackout window",
+                                db.name,
+                                due_time.format("%Y-%m-%d %H:%M:%S")
+                            ),
+                            BlackoutPolicy::Defer => {
+                                // Later fire times in the same window collapse into one run
+                                if deferred.insert(db.name.clone(), due_time).is_none() {
+                                    info!(
+                                        "Deferring backup for {} due at {} until its blackout window ends",
+                                        db.name,
+                                        due_time.format("%Y-%m-%d %H:%M:%S")
+                                    );
+                                }
+                            }
+                        }
+                    }
+
+                    if run_due.is_some() {
+                        deferred.remove(&db.name);
+                    } else if !db.in_blackout(&now) {
+                        if let Some(due_time) = deferred.remove(&db.name) {
+                            info!(
+                                "Blackout window over, running deferred backup for {}",
+                                db.name
+                            );
+                            run_due = Some(due_time);
+                        }
+                    }
+
+                    if let Some(due_time) = run_due {
+                        if due_time < window_start {
+                            info!(
+                                "Catching up on the backup for {} due at {}",
+                                db.name,
+                                due_time.format("%Y-%m-%d %H:%M:%S")
+                            );
+                        }
+                        if in_progress.contains_key(&db.name) {
+                            match db.overlap_policy {
+                                OverlapPolicy::Skip => {
+                                    skip_still_running(db);
+                                    mark_handled(&mut last_run_times, &db.name, due_time);
+                                    continue;
+                                }
+                                // Waits for the config lock the running one holds
+                                OverlapPolicy::Queue => {}
+                                OverlapPolicy::KillPrevious => {
+                                    if let Some(previous) = in_progress.remove(&db.name) {
+                                        warn!("Stopping the previous backup of {}", db.name);
+                                        previous.cancel.send(()).ok();
+                                    }
+                                }
+                            }
+                        }
+
+                        // Recorded in the state file once the backup finishes
+                        last_run_times.insert(db.name.clone(), due_time);
+                        next_run_id += 1;
+                        let id = next_run_id;
+                        let (cancel, cancelled) = tokio::sync::oneshot::channel::<()>();
+                        in_progress.insert(db.name.clone(), ScheduledRun { id, cancel });
+                        let (db, config) = (db.clone(), config.clone());
+                        // Dumps block their thread, as in `run --jobs`
+                        let runtime = tokio::runtime::Handle::current();
+                        running.spawn_blocking(move || {
+                            let finished = runtime.block_on(async {
+                                tokio::select! {
+                                    finished = run_scheduled_backup(&db, &config) => Some(finished),
+                                    _ = cancelled => None,
+                                }
+                            });
+                            FinishedRun {
+                                name: db.name,
+                                id,
+                                due_time,
+                                finished,
+                            }
+                        });
+                    }
+                }
+            }
+        }
+    }
+}
+
+/// Records `fire_time` as handled for `name`, in memory and in the state file
+/// for `daemon --catch-up` after a restart.
+fn mark_handled(
+    last_run_times: &mut std::collections::HashMap<String, chrono::DateTime<Local>>,
+    name: &str,
+    fire_time: chrono::DateTime<Local>,
+) {
+    last_run_times.insert(name.to_string(), fire_time);
+    if let Err(e) = record_scheduled_run(name, fire_time) {
+        warn!("Failed to save the last scheduled run of {}: {:#}", name, e);
+    }
+}
+
+/// How a scheduled backup ended, and how long it took.
+type ScheduledResult = (Result<BackupOutcome, BackupError>, Duration);
+
+/// Runs one scheduled backup, emitting its lifecycle events. Returns None
+/// when `overlap_policy` says to skip it because the previous run is still going.
+async fn run_scheduled_backup(db: &DatabaseConfig, config: &AppConfig) -> Option<ScheduledResult> {
+    // Another process (e.g. a manual run), or a queued run in this one,
+    // holds this config's lock
+    if config_is_busy(db) {
+        match db.overlap_policy {
+            OverlapPolicy::Skip => {
+                skip_still_running(db);
+                return None;
+            }
+            OverlapPolicy::Queue => info!(
+                "Backup of {} is still running; starting once it finishes",
+                db.name
+            ),
+            OverlapPolicy::KillPrevious => stop_lock_holder(db),
+        }
+    }
+    success!("Executing scheduled backup for {}", db.name);
+    events::emit(Event::BackupStarted { name: &db.name });
+    let started = std::time::Instant::now();
+    let opts = BackupOptions {
+        preflight: db.preflight_check,
+        ..BackupOptions::default()
+    };
+    let result = perform_backup(db, &opts).await;
+    match &result {
+        Ok(BackupOutcome::Created { bytes }) => events::emit(Event::BackupSucceeded {
+            name: &db.name,
+            size_bytes: *bytes,
+            duration_secs: started.elapsed().as_secs_f64(),
+        }),
+        Ok(BackupOutcome::Deduplicated) => events::emit(Event::BackupSkipped {
+            name: &db.name,
+            reason: "deduplicated",
+        }),
+        Ok(BackupOutcome::TooRecent) => events::emit(Event::BackupSkipped {
+            name: &db.name,
+            reason: "too_recent",
+        }),
+        Ok(BackupOutcome::NoMatches) => events::emit(Event::BackupSkipped {
+            name: &db.name,
+            reason: "no_matches",
+        }),
+        Err(e) => events::emit(Event::BackupFailed {
+            name: &db.name,
+            error: e.to_string(),
+        }),
+    }
+    notify_result(config, &db.name, &result);
+    Some((result, started.elapsed()))
+}
+
+fn skip_still_running(db: &DatabaseConfig) {
+    warn!(
+        "Skipping scheduled backup for {}: the previous one is still running",
+        db.name
+    );
+    events::emit(Event::BackupSkipped {
+        name: &db.name,
+        reason: "still_running",
+    });
+}
+
+/// Sends the configured webhook for a finished backup: always for a
+/// failure, and for a new backup with `notify_on_success`.
+fn notify_result(config: &AppConfig, name: &str, result: &Result<BackupOutcome, BackupError>) {
+    let Some(url) = &config.notify_webhook else {
+        return;
+    };
+    match result {
+        Err(e) => notify::backup_failed(url, name, &e.to_string()),
+        Ok(BackupOutcome::Created { bytes }) if config.notify_on_success => {
+            notify::backup_succeeded(url, name, *bytes)
+        }
+        Ok(_) => {}
+    }
+}
+
+/// One pass of the scheduler for running from system cron: backs up every
+/// config with a fire time since the one it last handled, then exits. The
+/// handled fire times are kept in the state file, so it doesn't matter how
+/// often cron invokes it; a config seen for the first time only looks back
+/// as far as the daemon would.
+async fn command_daemon_oneshot() -> Result<()> {
+    if let Some(pid) = fs::read_to_string(get_pid_path()?)
+        .ok()
+        .and_then(|p| p.trim().parse::<u32>().ok())
+    {
+        if process_is_running(pid) {
+            anyhow::bail!(
+                "Daemon is already running (PID {}); --oneshot would duplicate its backups",
+                pid
+            );
+        }
+    }
+
+    let config = load_config()?;
+    let state = load_state()?;
+    let now = Local::now();
+    let mut failed = 0;
+
+    for db in selectable_databases(&config, false) {
+        let Some(schedule) = db
+            .schedule
+            .as_deref()
+            .and_then(|s| Schedule::from_str(s).ok())
+        else {
+            continue;
+        };
+        let jitter = db.jitter_seconds.or(config.jitter_seconds).unwrap_or(0);
+        let last = state
+            .databases
+            .get(&db.name)
+            .and_then(|s| s.last_scheduled_run);
+        let search_start =
+            last.unwrap_or_else(|| now - chrono::Duration::seconds(61 + jitter as i64));
+        let due = schedule
+            .after(&search_start)
+            .take_while(|t| *t <= now)
+            .filter(|t| *t + jitter_offset(&db.name, t, jitter) <= now)
+            .last();
+
+        let Some(due_time) = due else {
+            if last.is_none() {
+                // Start counting from here so a later fire time isn't missed
+                record_scheduled_run(&db.name, search_start)?;
+            }
+            continue;
+        };
+        if db.in_blackout(&due_time) {
+            match db.blackout_policy {
+                BlackoutPolicy::Skip => {
+                    info!(
+                        "Skipping backup for {} due at {}: inside blackout window",
+                        db.name,
+                        due_time.format("%Y-%m-%d %H:%M:%S")
+                    );
+                    record_scheduled_run(&db.name, due_time)?;
+                    continue;
+                }
+                // Left unhandled so a later invocation picks it up once the window ends
+                BlackoutPolicy::Defer if db.in_blackout(&now) => {
+                    info!(
+                        "Deferring backup for {} due at {} until its blackout window ends",
+                        db.name,
+                        due_time.format("%Y-%m-%d %H:%M:%S")
+                    );
+                    continue;
+                }
+                BlackoutPolicy::Defer => {}
+            }
+        }
+
+        let result = run_scheduled_backup(db, &config).await;
+        record_scheduled_run(&db.name, due_time)?;
+        if let Some((Err(_), _)) = result {
+            failed += 1;
+        }
+    }
+
+    if failed > 0 {
+        anyhow::bail!("{} scheduled backup(s) failed", failed);
+    }
+    Ok(())
+}
+
+fn record_scheduled_run(name: &str, fire_time: chrono::DateTime<Local>) -> Result<()> {
+    update_state(|state| state.entry(name).last_scheduled_run = Some(fire_time))
+}
+
+/// Redraws the `daemon --watch` status table in place.
+fn render_dashboard(
+    databases: &[DatabaseConfig],
+    last_results: &std::collections::HashMap<String, LastRun>,
+) {
+    let mut table = new_table();
+    table.set_header(vec!["Name", "Next Run", "Last Run", "Result", "Duration"]);
+
+    for db in databases.iter().filter(|db| db.enabled) {
+        let next_run = db
+            .schedule
+            .as_deref()
+            .and_then(|s| Schedule::from_str(s).ok())
+            .and_then(|s| s.upcoming(Local).next())
+            .map(|t| t.format("%Y-%m-%d %H:%M:%S").to_string())
+            .unwrap_or_else(|| "Not scheduled".to_string());
+
+        let (last_run, result_cell, duration) = match last_results.get(&db.name) {
+            Some(run) => (
+                run.finished_at.format("%Y-%m-%d %H:%M:%S").to_string(),
+                if run.succeeded {
+                    Cell::new("OK").fg(Color::Green)
+                } else {
+                    Cell::new("Failed").fg(Color::Red)
+                },
+                format!("{:.1}s", run.duration.as_secs_f64()),
+            ),
+            None => ("-".to_string(), Cell::new("-"), "-".to_string()),
+        };
+
+        table.add_row(vec![
+            Cell::new(&db.name),
+            Cell::new(next_run),
+            Cell::new(last_run),
+            result_cell,
+            Cell::new(duration),
+        ]);
+    }
+
+    // Clear the screen and move the cursor home before redrawing
+    print!("\x1B[2J\x1B[H");
+    println!(
+        "dbr daemon - {} (Ctrl+C to stop)",
+        Local::now().format("%Y-%m-%d %H:%M:%S")
+    );
+    println!("{table}");
+}
+
+/// Delay applied to one scheduled fire time, spread over `0..jitter` seconds.
+///
+/// Derived from the config name and fire time rather than a fresh random
+/// number so every daemon tick agrees on when the backup becomes due.
+fn jitter_offset(name: &str, due_time: &chrono::DateTime<Local>, jitter: u64) -> chrono::Duration {
+    use std::hash::{Hash, Hasher};
+
+    if jitter == 0 {
+        return chrono::Duration::zero();
+    }
+    let mut hasher = std::collections::hash_map::DefaultHasher::new();
+    name.hash(&mut hasher);
+    due_time.timestamp().hash(&mut hasher);
+    chrono::Duration::seconds((hasher.finish() % jitter) as i64)
+}
+
+/// Per-invocation tweaks to how `perform_backup` behaves.
+#[derive(Debug, Default, Clone)]
+struct BackupOptions {
+    no_rotate: bool,
+    manual: bool,
+    /// Skip deduplication and the min_interval_seconds debounce
+    force: bool,
+    /// Appended to the dump tool's options for this invocation
+    dump_args: Vec<String>,
+    /// Show a percentage/ETA line on stderr while dumping
+    progress: bool,
+    /// Overrides the config's `read_rate_limit`, in bytes per second
+    limit_rate: Option<u64>,
+    /// Added to the file name after the timestamp, from `run --label`
+    label: Option<String>,
+    /// Wait for the server to answer first, as configured by `preflight_check`
+    preflight: bool,
+}
+
+/// What a successful `perform_backup` call left on disk.
+#[derive(Debug)]
+enum BackupOutcome {
+    Created {
+        bytes: u64,
+    },
+    /// The dump matched the previous backup and was discarded.
+    Deduplicated,
+    /// The previous backup is newer than `min_interval_seconds`, so none was taken.
+    TooRecent,
+    /// `database_pattern` matched no databases on the server.
+    NoMatches,
+}
+
+async fn perform_backup(
+    db: &DatabaseConfig,
+    opts: &BackupOptions,
+) -> Result<BackupOutcome, BackupError> {
+    logging::scope(&db.name, async {
+        let hook_env = vec![
+            ("DBR_NAME", db.name.clone()),
+            ("DBR_DATABASE", db.connection.database.clone()),
+        ];
+        let pre_hook = db
+            .pre_hook
+            .as_deref()
+            .map_or(Ok(()), |hook| run_hook(hook, &hook_env));
+        let result = match pre_hook {
+            Err(e) => Err(BackupError::Other(anyhow::anyhow!(
+                "pre_hook failed: {:#}",
+                e
+            ))),
+            Ok(()) => {
+                let result = async {
+                    if opts.preflight {
+                        wait_until_reachable(db).await?;
+                    }
+                    match &db.database_pattern {
+                        Some(pattern) => backup_matching(db, pattern, opts).await,
+                        None => create_backup(db, opts).await,
+                    }
+                }
+                .await;
+                run_post_hook(db, hook_env, result)
+            }
+        };
+        if let Err(e) = &result {
+            error!("Backup failed for {}: {}", db.name, e);
+        }
+        if let Err(e) = record_stats(db, &result) {
+            warn!("Failed to update statistics for {}: {}", db.name, e);
+        }
+        result
+    })
+    .await
+}
+
+/// Runs `db.post_hook`, if set, after a backup attempt that got past the
+/// pre-hook. Its failure only fails the backup with `post_hook_required`.
+fn run_post_hook(
+    db: &DatabaseConfig,
+    mut env: Vec<(&str, String)>,
+    result: Result<BackupOutcome, BackupError>,
+) -> Result<BackupOutcome, BackupError> {
+    let Some(hook) = &db.post_hook else {
+        return result;
+    };
+    let (status, file, error) = match &result {
+        // Pattern configs write one backup per matched database
+        Ok(BackupOutcome::Created { .. }) => (
+            "succeeded",
+            get_last_backup(db).filter(|_| db.database_pattern.is_none()),
+            String::new(),
+        ),
+        Ok(_) => ("skipped", None, String::new()),
+        Err(e) => ("failed", None, e.to_string()),
+    };
+    env.push(("DBR_STATUS", status.to_string()));
+    env.push((
+        "DBR_FILE",
+        file.map(|f| f.to_string_lossy().to_string())
+            .unwrap_or_default(),
+    ));
+    env.push(("DBR_ERROR", error));
+    match run_hook(hook, &env) {
+        Err(e) if db.post_hook_required && result.is_ok() => Err(BackupError::Other(
+            anyhow::anyhow!("post_hook failed: {:#}", e),
+        )),
+        Err(e) => {
+            warn!("post_hook failed for {}: {:#}", db.name, e);
+            result
+        }
+        Ok(()) => result,
+    }
+}
+
+/// Connectivity checks `preflight_check` makes before giving up, waiting
+/// `PREFLIGHT_FIRST_DELAY` and then twice as long each time in between.
+const PREFLIGHT_ATTEMPTS: u32 = 4;
+const PREFLIGHT_FIRST_DELAY: Duration = Duration::from_secs(5);
+
+/// Checks the server answers before a dump is started, riding out brief
+/// outages instead of spawning a dump that is bound to fail.
+async fn wait_until_reachable(db: &DatabaseConfig) -> Result<(), BackupError> {
+    let source = db.backup_source()?;
+    let database = match &db.database_pattern {
+        Some(_) => client::maintenance_database(&db.db_type),
+        None => &source.connection.database,
+    };
+    let mut delay = PREFLIGHT_FIRST_DELAY;
+    let mut attempt = 1;
+    loop {
+        let error = match client::check_connection(&db.db_type, &source.connection, database) {
+            Ok(()) => return Ok(()),
+            Err(e) => e,
+        };
+        if attempt == PREFLIGHT_ATTEMPTS {
+            return Err(BackupError::ConnectionFailed {
+                host: source.connection.host.clone(),
+                port: source.connection.port,
+                message: format!("still unreachable after {} attempts: {:#}", attempt, error),
+            });
+        }
+        warn!(
+            "{} is not reachable (attempt {}/{}): {:#}; retrying in {}s",
+            source.connection.host,
+            attempt,
+            PREFLIGHT_ATTEMPTS,
+            error,
+            delay.as_secs()
+        );
+        sleep(delay).await;
+        delay *= 2;
+        attempt += 1;
+    }
+}
+
+/// Backs up each database on the server matching `pattern` into its own
+/// subdirectory of `output_dir`, so databases created since the last run are
+/// picked up. Every match is attempted; the first failure is returned.
+async fn backup_matching(
+    db: &DatabaseConfig,
+    pattern: &str,
+    opts: &BackupOptions,
+) -> Result<BackupOutcome, BackupError> {
+    let source = db.backup_source()?;
+    let names: Vec<String> =
+        client::list_databases(&db.db_type, &source.connection, db.include_system_databases)?
+            .into_iter()
+            .filter(|name| client::glob_match(pattern, name))
+            .collect();
+    if names.is_empty() {
+        warn!(
+            "No databases on {} match '{}' for {}",
+            source.connection.host, pattern, db.name
+        );
+        return Ok(BackupOutcome::NoMatches);
+    }
+    info!(
+        "{} matches {} database(s): {}",
+        pattern,
+        names.len(),
+        names.join(", ")
+    );
+
+    let mut bytes = 0;
+    let mut created = false;
+    let mut all_too_recent = true;
+    let mut first_error = None;
+    // Each child gets backup_host applied again by create_backup
+    let target = db.resolved()?;
+    for name in names {
+        let mut child = target.clone();
+        child.database_pattern = None;
+        child.connection_url_env = None;
+        child.connection.database = name.clone();
+        child.output_dir = db.output_dir.join(file_stem(&name));
+        if let Err(e) = fs::create_dir_all(&child.output_dir) {
+            first_error.get_or_insert(BackupError::write(&child.output_dir, e));
+            continue;
+        }
+        match create_backup(&child, opts).await {
+            Ok(BackupOutcome::Created { bytes: b }) => {
+                created = true;
+                all_too_recent = false;
+                bytes += b;
+            }
+            Ok(BackupOutcome::TooRecent) => {}
+            Ok(_) => all_too_recent = false,
+            Err(e) => {
+                error!("Backup of database {} failed for {}: {}", name, db.name, e);
+                first_error.get_or_insert(e);
+            }
+        }
+    }
+
+    match first_error {
+        Some(e) => Err(e),
+        None if created => Ok(BackupOutcome::Created { bytes }),
+        None if all_too_recent => Ok(BackupOutcome::TooRecent),
+        None => Ok(BackupOutcome::Deduplicated),
+    }
+}
+
+fn record_stats(db: &DatabaseConfig, result: &Result<BackupOutcome, BackupError>) -> Result<()> {
+    match result {
+        Ok(BackupOutcome::Created { bytes }) => {
+            update_state(|state| state.entry(&db.name).stats.record_success(*bytes))
+        }
+        Ok(BackupOutcome::Deduplicated) => {
+            update_state(|state| state.entry(&db.name).stats.last_unchanged = Some(Local::now()))
+        }
+        Ok(BackupOutcome::TooRecent | BackupOutcome::NoMatches) => Ok(()),
+        Err(_) => update_state(|state| state.entry(&db.name).stats.record_failure()),
+    }
+}
+
+/// Whether the SQLite file is as it was at the last backup, judged by size
+/// and modification time alone so an unchanged file is never read.
+fn unchanged_since_last(db: &DatabaseConfig, source: &SourceFile) -> bool {
+    get_last_backup(db)
+        .and_then(|last| Manifest::read(&last).ok())
+        .and_then(|manifest| manifest.source_file)
+        .is_some_and(|last| last == *source)
+}
+
+async fn create_backup(
+    db: &DatabaseConfig,
+    opts: &BackupOptions,
+) -> Result<BackupOutcome, BackupError> {
+    // Maintenance goes to the configured server, even when dumping from a replica
+    let maintenance_target = match db.post_backup_maintenance {
+        Some(_) => Some(db.resolved()?.connection),
+        None => None,
+    };
+    let db = &db.backup_source()?;
+    let _lock = lock_config(db).await?;
+
+    // Debounce restarts and overlapping schedules. Manual, labeled and forced runs are always taken.
+    let deliberate = opts.manual || opts.force || opts.label.is_some();
+    if let (Some(min_interval), false) = (db.min_interval_seconds, deliberate) {
+        if let Some(last) = get_last_backup(db) {
+            let age = Local::now().naive_local() - backup_timestamp(db, &last);
+            if age < chrono::Duration::seconds(min_interval as i64) {
+                success!(
+                    "Backup skipped for {}: last backup is only {}s old (min_interval_seconds = {})",
+                    db.name,
+                    age.num_seconds(),
+                    min_interval
+                );
+                return Ok(BackupOutcome::TooRecent);
+            }
+        }
+    }
+
+    // The source file's size and modification time, for SQLite's `since_last` check
+    let source_file = match db.db_type {
+        DbType::SQLite => Some(
+            SourceFile::read(std::path::Path::new(&db.connection.database)).with_context(|| {
+                format!("Cannot read SQLite database {}", db.connection.database)
+            })?,
+        ),
+        _ => None,
+    };
+    if let (Some(source), true) = (&source_file, db.since_last && !deliberate) {
+        if unchanged_since_last(db, source) {
+            success!(
+                "Backup skipped for {}: database file unchanged since the last backup",
+                db.name
+            );
+            return Ok(BackupOutcome::Deduplicated);
+        }
+    }
+
+    // A SQLite file has no server to share
+    let _host_slot = match CONCURRENCY_PER_HOST.load(Ordering::Relaxed) {
+        0 => None,
+        _ if db.db_type == DbType::SQLite => None,
+        limit => Some(acquire_host_slot(&db.connection, limit).await?),
+    };
+
+    success!("Backing up database: {}", db.name);
+
+    if db.require_mountpoint {
+        check_mounted(&db.output_dir)?;
+    }
+    if !db.allow_shared_output_dir {
+        check_dedicated(&db.output_dir, opts.force)?;
+    }
+
+    if !db.output_dir.exists() {
+        fs::create_dir_all(&db.output_dir)?;
+    }
+
+    let use_mydumper = db.dump_tool == DumpTool::Mydumper && {
+        let found = mydumper_available();
+        if !found {
+            warn!(
+                "mydumper is not installed; backing up {} with mysqldump",
+                db.name
+            );
+        }
+        found
+    };
+    let copy_sqlite = db.db_type == DbType::SQLite && {
+        let found = sqlite3_available();
+        if !found {
+            warn!(
+                "sqlite3 is not installed; copying the database file for {} instead of dumping it",
+                db.name
+            );
+        }
+        !found
+    };
+    let extension = if use_mydumper {
+        MYDUMPER_EXTENSION
+    } else if copy_sqlite {
+        SQLITE_EXTENSION
+    } else {
+        backup_extension(db)
+    };
+
+    let mut tag = if opts.manual { MANUAL_SUFFIX } else { "" }.to_string();
+    if let Some(label) = &opts.label {
+        tag.push_str(LABEL_SEPARATOR);
+        tag.push_str(label);
+    }
+    let output_path = new_backup_path(db, &Local::now(), &tag, extension);
+    let filename = output_path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    // Dump under a name rotation and listing ignore, then move it into place
+    let temp_path = temp_backup_path(&output_path);
+
+    // mydumper writes many files itself, so there is no stream to measure
+    let progress = (opts.progress && !use_mydumper && !copy_sqlite)
+        .then(|| progress::Progress::start(&db.name, previous_backup_size(db)));
+    let counter = progress.as_ref().map(|p| p.counter());
+    let rate_limit = opts.limit_rate.or(db.read_rate_limit);
+    let mut dump_hash = None;
+    // Set when the new entry was linked to an identical backup instead of kept as a copy
+    let mut linked = false;
+
+    match db.db_type {
+        DbType::MariaDB if use_mydumper => {
+            run_mydumper(db, &temp_path, &opts.dump_args).inspect_err(|_| {
+                split::remove(&temp_path).ok();
+            })?;
+        }
+        DbType::MariaDB => {
+            // First attempt: Standard backup. Only a failing dump is worth retrying;
+            // a missing tool, unreachable server or full disk will fail again.
+            if let Err(e) = run_mysqldump(
+                db,
+                &temp_path,
+                false,
+                &opts.dump_args,
+                counter.clone(),
+                rate_limit,
+            )
+            .await
+            {
+                // A dump that hung is most likely waiting on a table lock
+                if !matches!(
+                    e,
+                    BackupError::DumpFailed { .. } | BackupError::TimedOut { .. }
+                ) {
+                    split::remove(&temp_path).ok();
+                    return Err(e);
+                }
+                warn!(
+                    "Standard backup failed for {}. Retrying with --skip-lock-tables. Error: {}",
+                    db.name, e
+                );
+
+                // The retry writes to the same path; drop what the failed attempt left,
+                // or stale trailing parts of a split backup would be picked up with it
+                split::remove(&temp_path).ok();
+                if let Some(counter) = &counter {
+                    counter.store(0, Ordering::Relaxed);
+                }
+                if let Err(retry_err) = run_mysqldump(
+                    db,
+                    &temp_path,
+                    true,
+                    &opts.dump_args,
+                    counter.clone(),
+                    rate_limit,
+                )
+                .await
+                {
+                    error!("Retry with --skip-lock-tables also failed for {}", db.name);
+                    split::remove(&temp_path).ok(); // Cleanup incomplete file
+                    return Err(retry_err);
+                } else {
+                    info!("Backup succeeded with --skip-lock-tables for {}", db.name);
+                }
+            }
+        }
+        DbType::PostgreSQL => {
+            let mut commands = dump_commands(db, false, &opts.dump_args);
+
+            // Prefer pg_dump's built-in compression over gzipping the stream ourselves,
+            // unless parallel compression was asked for or other output is appended
+            let compression = match stream_compression(db) {
+                StreamCompression::Gzip if commands.len() == 1 => {
+                    for c in &mut commands {
+                        c.arg("-Z9");
+                    }
+                    StreamCompression::None
+                }
+                other => other,
+            };
+
+            let output = run_dump(
+                commands,
+                &temp_path,
+                compression,
+                db.split_size,
+                counter,
+                rate_limit,
+                db.timeout_secs,
+            )
+            .await
+            .inspect_err(|_| {
+                split::remove(&temp_path).ok();
+            })?;
+            if !output.status.success() {
+                split::remove(&temp_path).ok();
+                return Err(BackupError::dump_failed(
+                    "pg_dump",
+                    &db.connection.host,
+                    db.connection.port,
+                    &db.connection.database,
+                    &output.stderr,
+                ));
+            }
+        }
+        DbType::SQLite if copy_sqlite => {
+            copy_sqlite_file(db, &temp_path).inspect_err(|_| {
+                fs::remove_file(&temp_path).ok();
+            })?;
+        }
+        DbType::SQLite => {
+            let output = run_dump(
+                dump_commands(db, false, &opts.dump_args),
+                &temp_path,
+                stream_compression(db),
+                db.split_size,
+                counter,
+                rate_limit,
+                db.timeout_secs,
+            )
+            .await
+            .inspect_err(|_| {
+                split::remove(&temp_path).ok();
+            })?;
+            if !output.status.success() {
+                split::remove(&temp_path).ok();
+                return Err(BackupError::DumpFailed {
+                    tool: "sqlite3".to_string(),
+                    message: String::from_utf8_lossy(&output.stderr).trim().to_string(),
+                });
+            }
+        }
+    }
+
+    // Manual and labeled snapshots are deliberate, so always keep them
+    let dedup = db.deduplicate
+        && !deliberate
+        && !use_mydumper
+        && matches!(db.db_type, DbType::MariaDB | DbType::PostgreSQL);
+    if dedup {
+        match deduplicate_dump(db, &temp_path, &output_path) {
+            Some((was_linked, hash)) => {
+                linked = was_linked;
+                dump_hash = hash;
+            }
+            None => return Ok(BackupOutcome::Deduplicated),
+        }
+    }
+
+    drop(progress);
+    if linked {
+        split::remove(&temp_path).ok();
+    } else {
+        split::rename(&temp_path, &output_path)?;
+    }
+    if db.storage_layout == StorageLayout::ContentAddressed {
+        let hash = match dump_hash.clone() {
+            Some(hash) => Ok(hash),
+            None => content_hash(&output_path),
+        };
+        let stored = hash.and_then(|hash| {
+            blobs::intern(&db.blob_dir(), &output_path, &hash, extension)?;
+            dump_hash = Some(hash);
+            Ok(())
+        });
+        if let Err(e) = stored {
+            warn!("Keeping {:?} outside the blob store: {:#}", output_path, e);
+        }
+    }
+    success!("Backup created at: {:?}", output_path);
+
+    let restore_check = db.verify_restore.as_ref().map(|scratch| {
+        let check = test_restore(db, scratch, &output_path);
+        if check.passed {
+            success!("Test restore passed for {}", db.name);
+        } else {
+            error!(
+                "Test restore failed for {}: {}",
+                db.name,
+                check.error.as_deref().unwrap_or("unknown error")
+            );
+        }
+        check
+    });
+
+    let binlog_position = if db.record_binlog_position {
+        let position = read_binlog_position(&output_path);
+        match &position {
+            Ok(Some(position)) => info!("Binlog position for {}: {}", db.name, position),
+            Ok(None) => warn!(
+                "No binlog position found in {:?}; is binary logging enabled on the server?",
+                output_path
+            ),
+            Err(e) => warn!(
+                "Could not read binlog position from {:?}: {:#}",
+                output_path, e
+            ),
+        }
+        position.ok().flatten()
+    } else {
+        None
+    };
+
+    let size_bytes = split::total_size(&output_path)?;
+    if let Some(percent) = db.size_anomaly_percent {
+        check_size_anomaly(db, &output_path, size_bytes, percent);
+    }
+    let manifest = Manifest {
+        config_name: db.name.clone(),
+        db_type: db.db_type.clone(),
+        database: db.connection.database.clone(),
+        file: filename,
+        created_at: Local::now(),
+        size_bytes,
+        // A copied SQLite file is stored as is
+        compression: if copy_sqlite {
+            Compression::None
+        } else {
+            db.compression
+        },
+        restore_check,
+        content_hash: dump_hash,
+        binlog_position,
+        remote_data_key: None,
+        source_file,
+    };
+    if let Err(e) = manifest.write(&output_path) {
+        warn!("{}", e);
+    }
+    if let Err(e) = manifest::write_checksum(&output_path) {
+        warn!("{:#}", e);
+    }
+    if db.include_server_config {
+        if let Err(e) = write_server_config(db, &output_path) {
+            warn!(
+                "Could not save the server configuration for {}: {:#}",
+                db.name, e
+            );
+        }
+    }
+
+    if db.fsync {
+        sync_backup(&output_path).map_err(|e| BackupError::write(&output_path, e))?;
+    }
+
+    if let Some(remote) = &db.remote {
+        upload_backup(db, remote, &output_path).map_err(|e| BackupError::RemoteFailed {
+            message: format!("{:#}", e),
+        })?;
+        if let Err(e) = rotate_remote(db, remote) {
+            warn!("Remote rotation failed for {}: {:#}", db.name, e);
+        }
+    }
+
+    if !opts.no_rotate {
+        let rotation = rotate_backups(db)?;
+        if !rotation.removed.is_empty() {
+            info!(
+                "Rotation for {} removed {} backup(s), freeing {}",
+                db.name,
+                rotation.removed.len(),
+                format_bytes(rotation.bytes_freed)
+            );
+            events::emit(Event::RotationPerformed {
+                name: &db.name,
+                removed: rotation
+                    .removed
+                    .iter()
+                    .map(|p| p.to_string_lossy().to_string())
+                    .collect(),
+                bytes_freed: rotation.bytes_freed,
+            });
+            if let Some(hook) = &db.post_rotate_hook {
+                let files: Vec<String> = rotation
+                    .removed
+                    .iter()
+                    .map(|p| p.to_string_lossy().to_string())
+                    .collect();
+                let env = [
+                    ("DBR_NAME", db.name.clone()),
+                    ("DBR_REMOVED_COUNT", rotation.removed.len().to_string()),
+                    ("DBR_BYTES_FREED", rotation.bytes_freed.to_string()),
+                    ("DBR_REMOVED_FILES", files.join("\n")),
+                ];
+                if let Err(e) = run_hook(hook, &env) {
+                    warn!("post_rotate_hook failed for {}: {:#}", db.name, e);
+                }
+            }
+        }
+    }
+
+    if let (Some(maintenance), Some(conn)) = (db.post_backup_maintenance, &maintenance_target) {
+        info!(
+            "Running {} maintenance on {} for {}",
+            maintenance, db.connection.database, db.name
+        );
+        let started = std::time::Instant::now();
+        match client::run_maintenance(&db.db_type, conn, &db.connection.database, maintenance) {
+            Ok(()) => info!(
+                "{} maintenance for {} finished in {:.1}s",
+                maintenance,
+                db.name,
+                started.elapsed().as_secs_f64()
+            ),
+            Err(e) => warn!(
+                "{} maintenance failed for {}: {:#}",
+                maintenance, db.name, e
+            ),
+        }
+    }
+
+    Ok(BackupOutcome::Created { bytes: size_bytes })
+}
+
+/// How many earlier backups' sizes `size_anomaly_percent` averages over.
+const SIZE_HISTORY: usize = 5;
+
+/// Warns when `size_bytes` is more than `percent` away from the average size
+/// of the previous few backups, as recorded in their manifests.
+fn check_size_anomaly(db: &DatabaseConfig, new: &std::path::Path, size_bytes: u64, percent: u32) {
+    let history: Vec<u64> = list_backups(db)
+        .iter()
+        .rev()
+        .filter(|p| p.as_path() != new)
+        .take(SIZE_HISTORY)
+        .filter_map(|p| Manifest::read(p).ok())
+        .map(|m| m.size_bytes)
+        .collect();
+    if history.is_empty() {
+        return;
+    }
+    let average = history.iter().sum::<u64>() / history.len() as u64;
+    let deviation = size_bytes.abs_diff(average) as f64 * 100.0 / average.max(1) as f64;
+    if deviation <= percent as f64 {
+        return;
+    }
+    warn!(
+        "Backup of {} is {} where the last {} averaged {} ({:.0}% {}); check for truncated or runaway data",
+        db.name,
+        format_bytes(size_bytes),
+        history.len(),
+        format_bytes(average),
+        deviation,
+        if size_bytes > average { "larger" } else { "smaller" }
+    );
+    events::emit(Event::SizeAnomaly {
+        name: &db.name,
+        size_bytes,
+        average_bytes: average,
+    });
+}
+
+/// Path for a backup taken at `now`. If a backup (or an in-progress one)
+/// already has that name, `filename_collision` decides how to tell the new
+/// one apart rather than overwriting it.
+fn new_backup_path(
+    db: &DatabaseConfig,
+    now: &chrono::DateTime<Local>,
+    tag: &str,
+    extension: &str,
+) -> PathBuf {
+    let path = |stamp: &str| {
+        db.output_dir
+            .join(format!("{}_{}{}.{}", db.name, stamp, tag, extension))
+    };
+    let taken = |stamp: &str| {
+        let path = path(stamp);
+        split::exists(&path) || split::exists(&temp_backup_path(&path))
+    };
+
+    let mut stamp = now.format(db.timestamp_format()).to_string();
+    if taken(&stamp) && db.filename_collision == FilenameCollision::Milliseconds {
+        stamp = format!("{}.{:03}", stamp, now.timestamp_subsec_millis());
+    }
+    let mut candidate = stamp.clone();
+    let mut counter = 0;
+    while taken(&candidate) {
+        counter += 1;
+        candidate = format!("{}_{}", stamp, counter);
+    }
+    path(&candidate)
+}
+
+/// Where a backup is written before it is complete.
+fn temp_backup_path(output_path: &std::path::Path) -> PathBuf {
+    let mut name = output_path.as_os_str().to_os_string();
+    name.push(".tmp");
+    PathBuf::from(name)
+}
+
+fn get_lock_path(name: &str) -> Result<PathBuf> {
+    let dir = get_profile_path("locks")?;
+    fs::create_dir_all(&dir)?;
+    Ok(dir.join(format!("{}.lock", file_stem(name))))
+}
+
+/// A config name made safe to use as a file name.
+fn file_stem(name: &str) -> String {
+    name.chars()
+        .map(|c| {
+            if c.is_alphanumeric() || c == '-' {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// Takes the per-config lock, waiting if another process holds it, so
+/// overlapping runs (e.g. manual and scheduled) of the same config don't
+/// interleave dumping and rotation. Released when the file is dropped.
+async fn lock_config(db: &DatabaseConfig) -> Result<fs::File> {
+    let path = get_lock_path(&db.name)?;
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .open(&path)
+        .with_context(|| format!("Failed to open lock file {:?}", path))?;
+    match file.try_lock() {
+        Ok(()) => {}
+        Err(fs::TryLockError::WouldBlock) => {
+            info!("Waiting for another backup of {} to finish", db.name);
+            // Waiting on a blocking thread keeps the runtime free for other backups
+            file = tokio::task::spawn_blocking(move || file.lock().map(|()| file)).await??;
+        }
+        Err(fs::TryLockError::Error(e)) => return Err(e.into()),
+    }
+    // Record the holder so `overlap_policy = KillPrevious` knows whom to stop
+    file.set_len(0)?;
+    write!(file, "{}", std::process::id())?;
+    Ok(file)
+}
+
+/// How often a backup waiting for `--concurrency-per-host` checks for a free slot.
+const HOST_SLOT_POLL: Duration = Duration::from_secs(2);
+
+/// Takes one of `limit` slots for dumping from `conn`'s host, waiting until
+/// one is free. Slots are lock files shared by every config file's
+/// processes, so separate daemons and manual runs count towards one limit.
+/// The slot is held until the returned file is dropped.
+async fn acquire_host_slot(conn: &ConnectionDetails, limit: usize) -> Result<fs::File> {
+    let dir = get_config_path()?.with_file_name("host-locks");
+    fs::create_dir_all(&dir)?;
+    let host = file_stem(&format!("{}_{}", conn.host, conn.port));
+    let mut waiting = false;
+    loop {
+        for slot in 0..limit {
+            let path = dir.join(format!("{}.{}.lock", host, slot));
+            let file = fs::OpenOptions::new()
+                .create(true)
+                .truncate(false)
+                .write(true)
+                .open(&path)
+                .with_context(|| format!("Failed to open lock file {:?}", path))?;
+            match file.try_lock() {
+                Ok(()) => return Ok(file),
+                Err(fs::TryLockError::WouldBlock) => {}
+                Err(fs::TryLockError::Error(e)) => return Err(e.into()),
+            }
+        }
+        if !waiting {
+            info!(
+                "Waiting for one of the {} backup(s) running against {}:{} to finish",
+                limit, conn.host, conn.port
+            );
+            waiting = true;
+        }
+        sleep(HOST_SLOT_POLL).await;
+    }
+}
+
+/// Terminates the process holding `db`'s lock, so a due backup can replace it.
+fn stop_lock_holder(db: &DatabaseConfig) {
+    let pid = get_lock_path(&db.name)
+        .ok()
+        .and_then(|path| fs::read_to_string(path).ok())
+        .and_then(|pid| pid.trim().parse::<u32>().ok());
+    if pid == Some(std::process::id()) {
+        // A run of the daemon's own that it has already stopped, still letting go
+        return;
+    }
+    let Some(pid) = pid else {
+        warn!(
+            "Could not tell which process is backing up {}; waiting for it instead",
+            db.name
+        );
+        return;
+    };
+    warn!("Stopping the previous backup of {} (PID {})", db.name, pid);
+    #[cfg(unix)]
+    let status = Command::new("kill").arg(pid.to_string()).status();
+    #[cfg(not(unix))]
+    let status = Command::new("taskkill")
+        .args(["/PID", &pid.to_string(), "/F"])
+        .status();
+    if !status.is_ok_and(|s| s.success()) {
+        warn!("Failed to stop PID {}; waiting for it instead", pid);
+    }
+}
+
+/// On-disk size of the previous backup, used to estimate progress.
+fn previous_backup_size(db: &DatabaseConfig) -> Option<u64> {
+    let last = get_last_backup(db)?;
+    Manifest::read(&last)
+        .map(|m| m.size_bytes)
+        .or_else(|_| split::total_size(&last))
+        .ok()
+}
+
+/// Writes the settings of the server `db` was dumped from next to the backup.
+fn write_server_config(db: &DatabaseConfig, backup: &std::path::Path) -> Result<()> {
+    let settings = client::server_settings(&db.db_type, &db.connection)?;
+    let path = manifest::server_config_path(backup);
+    let header = format!(
+        "# Server configuration of {}:{} for {}, captured {}\n",
+        db.connection.host,
+        db.connection.port,
+        db.name,
+        Local::now().format("%Y-%m-%d %H:%M:%S %:z")
+    );
+    fs::write(&path, header + &settings + "\n")
+        .with_context(|| format!("Failed to write {:?}", path))?;
+    debug!("Saved server configuration to {:?}", path);
+    Ok(())
+}
+
+/// Forces a backup's files, its manifest and the directory entries pointing
+/// at them out of the page cache so they survive a crash or power loss.
+fn sync_backup(path: &std::path::Path) -> std::io::Result<()> {
+    for part in split::backup_parts(path) {
+        fs::File::open(part)?.sync_all()?;
+    }
+    for sidecar in [manifest::manifest_path(path), manifest::checksum_path(path)] {
+        if sidecar.exists() {
+            fs::File::open(sidecar)?.sync_all()?;
+        }
+    }
+    // Directories can only be opened for syncing on Unix
+    #[cfg(unix)]
+    if path.is_dir() {
+        fs::File::open(path)?.sync_all()?;
+    }
+    #[cfg(unix)]
+    if let Some(dir) = path.parent() {
+        fs::File::open(dir)?.sync_all()?;
+    }
+    Ok(())
+}
+
+/// Name of the marker file that proves `output_dir` is the intended mount.
+const MOUNT_MARKER: &str = ".dbr-mount";
+
+/// How many files that aren't backups an output directory may hold before
+/// it looks like somewhere shared, such as a home directory.
+const UNRELATED_FILES_LIMIT: usize = 10;
+
+/// Files in `dir` that no config would have written: not a backup, one of
+/// its parts, an in-progress dump or a manifest.
+fn unrelated_files(dir: &std::path::Path) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(dir) else {
+        return Vec::new();
+    };
+    entries
+        .filter_map(|e| e.ok())
+        .filter(|e| e.file_type().is_ok_and(|t| t.is_file()))
+        .filter(|e| {
+            let name = e.file_name().to_string_lossy().to_string();
+            let name = name.strip_suffix(".json").unwrap_or(&name);
+            let name = name.strip_suffix(manifest::CHECKSUM_SUFFIX).unwrap_or(name);
+            let name = split::logical_name(name);
+            let name = name.strip_suffix(".tmp").unwrap_or(name);
+            name != MOUNT_MARKER
+                && !is_backup_file_name(name)
+                && !name.ends_with(manifest::SERVER_CONFIG_SUFFIX)
+        })
+        .map(|e| e.path())
+        .collect()
+}
+
+/// Refuses to back up into a directory that is mostly other people's files,
+/// where a backup name clash could make rotation delete something it
+/// shouldn't. `force` downgrades the refusal to a warning.
+fn check_dedicated(dir: &std::path::Path, force: bool) -> Result<()> {
+    let unrelated = unrelated_files(dir).len();
+    if unrelated <= UNRELATED_FILES_LIMIT {
+        return Ok(());
+    }
+    let problem = format!(
+        "Output directory {:?} contains {} files that aren't backups; use a directory dedicated to backups",
+        dir, unrelated
+    );
+    if force {
+        warn!("{}", problem);
+        return Ok(());
+    }
+    anyhow::bail!(
+        "{} (or set allow_shared_output_dir = true, or pass --force)",
+        problem
+    );
+}
+
+/// Refuses to continue unless `dir` is a mountpoint or contains the marker
+/// file, so an unmounted share doesn't silently fill the local disk.
+fn check_mounted(dir: &std::path::Path) -> Result<()> {
+    if !dir.is_dir() {
+        anyhow::bail!(
+            "Output directory {:?} does not exist; is the backup volume mounted?",
+            dir
+        );
+    }
+    if dir.join(MOUNT_MARKER).exists() {
+        return Ok(());
+    }
+
+    #[cfg(unix)]
+    {
+        use std::os::unix::fs::MetadataExt;
+        // A mountpoint lives on a different device than its parent directory
+        let dev = fs::metadata(dir)?.dev();
+        let parent_dev = fs::metadata(dir.join(".."))?.dev();
+        if dev != parent_dev {
+            return Ok(());
+        }
+    }
+
+    anyhow::bail!(
+        "Output directory {:?} is not a mountpoint and has no {} marker; skipping backup",
+        dir,
+        MOUNT_MARKER
+    );
+}
+
+/// Restores a fresh backup into the scratch database, checks it, then drops it.
+fn test_restore(
+    db: &DatabaseConfig,
+    scratch: &ConnectionDetails,
+    backup_path: &std::path::Path,
+) -> RestoreCheck {
+    let db_type = &db.db_type;
+    let server_db = client::maintenance_database(db_type);
+    let quoted = client::quote_ident(db_type, &scratch.database);
+    let drop_sql = format!("DROP DATABASE IF EXISTS {}", quoted);
+
+    let result = (|| -> Result<u64> {
+        if scratch.host == db.connection.host
+            && scratch.port == db.connection.port
+            && scratch.database == db.connection.database
+        {
+            anyhow::bail!("Scratch database must differ from the database being backed up");
+        }
+
+        // Start from a clean slate in case a previous check was interrupted
+        client::run_sql(db_type, scratch, server_db, &drop_sql)?;
+        client::run_sql(
+            db_type,
+            scratch,
+            server_db,
+            &format!("CREATE DATABASE {}", quoted),
+        )?;
+        client::restore_file(db_type, scratch, &scratch.database, backup_path)?;
+
+        let count_sql = match db_type {
+            DbType::MariaDB => {
+                "SELECT COUNT(*) FROM information_schema.tables WHERE table_schema = DATABASE()"
+            }
+            DbType::PostgreSQL => {
+                "SELECT count(*) FROM information_schema.tables \
+                 WHERE table_schema NOT IN ('pg_catalog', 'information_schema')"
+            }
+            DbType::SQLite => "SELECT count(*) FROM sqlite_master WHERE type = 'table'",
+        };
+        let tables = client::run_sql(db_type, scratch, &scratch.database, count_sql)?;
+        tables
+            .parse::<u64>()
+            .with_context(|| format!("Unexpected table count output: {}", tables))
+    })();
+
+    if let Err(e) = client::run_sql(db_type, scratch, server_db, &drop_sql) {
+        warn!(
+            "Failed to drop scratch database {}: {}",
+            scratch.database, e
+        );
+    }
+
+    match result {
+        Ok(tables) => RestoreCheck {
+            passed: true,
+            checked_at: Local::now(),
+            tables: Some(tables),
+            error: None,
+        },
+        Err(e) => RestoreCheck {
+            passed: false,
+            checked_at: Local::now(),
+            tables: None,
+            error: Some(e.to_string()),
+        },
+    }
+}
+
+/// The commands that dump `db`. `extra_args` (from `run --dump-args`) go
+/// after the config's own `extra_args`.
+fn dump_commands(db: &DatabaseConfig, skip_lock: bool, extra_args: &[String]) -> Vec<Command> {
+    let extra_args: Vec<String> = db.extra_args.iter().chain(extra_args).cloned().collect();
+    build_dump_commands(db, skip_lock, &extra_args)
+        .into_iter()
+        .map(|c| with_priority(db, c))
+        .collect()
+}
+
+/// Builds the `mysqldump`/`pg_dump` invocations for `db`. Their outputs are
+/// concatenated to form one dump; redirection and compression are left to
+/// the caller.
+fn build_dump_commands(
+    db: &DatabaseConfig,
+    skip_lock: bool,
+    extra_args: &[String],
+) -> Vec<Command> {
+    match db.db_type {
+        DbType::MariaDB => {
+            let mut c = mysqldump_command(db, skip_lock, extra_args);
+
+            if db.schema_only {
+                c.arg("--no-data");
+            } else if db.data_only {
+                c.arg("--no-create-info");
+            }
+
+            // Routines and events belong to the database, not a table, so
+            // they only go in the main pass
+            if !db.data_only {
+                if db.include_routines {
+                    c.arg("--routines");
+                }
+                if db.include_events {
+                    c.arg("--events");
+                }
+            }
+
+            if let Some(clause) = &db.where_clause {
+                c.arg(format!("--where={}", clause));
+            }
+
+            // Only the main pass: its snapshot is the one the position belongs to
+            if db.record_binlog_position {
+                c.arg("--master-data=2").arg("--flush-logs");
+                if !skip_lock {
+                    c.arg("--single-transaction");
+                }
+            }
+
+            for table in &db.exclude_tables {
+                c.arg(format!(
+                    "--ignore-table={}.{}",
+                    db.connection.database, table
+                ));
+            }
+
+            if db.schema_only || db.schema_only_tables.is_empty() {
+                c.arg(&db.connection.database).args(&db.include_tables);
+                return vec![c];
+            }
+
+            // mysqldump can't drop data per table, so dump those tables in a
+            // second, structure-only pass
+            for table in &db.schema_only_tables {
+                c.arg(format!(
+                    "--ignore-table={}.{}",
+                    db.connection.database, table
+                ));
+            }
+            c.arg(&db.connection.database);
+            if db.data_only {
+                return vec![c];
+            }
+
+            let mut schema_pass = mysqldump_command(db, skip_lock, extra_args);
+            schema_pass
+                .arg("--no-data")
+                .arg(&db.connection.database)
+                .args(&db.schema_only_tables);
+            vec![c, schema_pass]
+        }
+        DbType::PostgreSQL => {
+            let mut c = client::tool("pg_dump");
+            c.envs(&db.env);
+            c.env("PGHOST", &db.connection.host)
+                .env("PGPORT", db.connection.port.to_string())
+                .env("PGUSER", &db.connection.user)
+                .env("PGDATABASE", &db.connection.database);
+            client::set_password(&mut c, &db.db_type, db.connection.password.as_deref());
+            c.arg(format!("--encoding={}", db.charset()));
+
+            if db.schema_only {
+                c.arg("--schema-only");
+            } else if db.data_only {
+                c.arg("--data-only");
+            }
+
+            if !db.schema_only {
+                for table in &db.schema_only_tables {
+                    c.arg(format!("--exclude-table-data={}", table));
+                }
+            }
+            for table in &db.include_tables {
+                c.arg(format!("--table={}", table));
+            }
+            for table in &db.exclude_tables {
+                c.arg(format!("--exclude-table={}", table));
+            }
+            c.args(extra_args);
+
+            let (Some(clause), [table]) = (&db.where_clause, db.include_tables.as_slice()) else {
+                return vec![c];
+            };
+            // pg_dump can't filter rows: dump the table's definition, then append
+            // its matching rows as a COPY block like the one pg_dump would write
+            c.arg(format!("--exclude-table-data={}", table));
+            let mut rows =
+                client::client_command(&db.db_type, &db.connection, &db.connection.database);
+            rows.envs(&db.env)
+                .arg("-X")
+                .arg("-c")
+                .arg(format!(
+                    "\\echo 'COPY {} FROM stdin;'",
+                    table.replace('\'', "\\'")
+                ))
+                .arg("-c")
+                .arg(format!(
+                    "COPY (SELECT * FROM {} WHERE {}) TO STDOUT",
+                    table, clause
+                ))
+                .arg("-c")
+                .arg("\\echo '\\\\.'");
+            vec![c, rows]
+        }
+        DbType::SQLite => {
+            let mut c = client::tool("sqlite3");
+            c.envs(&db.env);
+            c.arg("-readonly")
+                .args(extra_args)
+                .arg(&db.connection.database);
+            if db.schema_only {
+                c.arg(".schema");
+            } else {
+                c.arg(format!(".dump {}", db.include_tables.join(" ")).trim_end());
+            }
+            vec![c]
+        }
+    }
+}
+
+/// Wraps a dump command in `nice`/`ionice` as configured, so it yields CPU
+/// and disk to the server's real workload.
+fn with_priority(db: &DatabaseConfig, c: Command) -> Command {
+    let mut argv: Vec<std::ffi::OsString> = Vec::new();
+    if let Some(level) = db.nice_level {
+        argv.push(client::tool("nice").get_program().into());
+        argv.extend(["-n".into(), level.to_string().into()]);
+    }
+    if let Some(class) = db.ionice_class {
+        if ionice_available() {
+            argv.push(client::tool("ionice").get_program().into());
+            argv.extend(["-c".into(), class.number().to_string().into()]);
+        }
+    }
+    if argv.is_empty() {
+        return c;
+    }
+
+    argv.push(c.get_program().into());
+    argv.extend(c.get_args().map(Into::into));
+    let mut wrapped = Command::new(&argv[0]);
+    wrapped.args(&argv[1..]);
+    for (key, value) in c.get_envs() {
+        match value {
+            Some(value) => wrapped.env(key, value),
+            None => wrapped.env_remove(key),
+        };
+    }
+    if let Some(dir) = c.get_current_dir() {
+        wrapped.current_dir(dir);
+    }
+    wrapped
+}
+
+/// Whether `ionice` can be run, checked once and warned about if not.
+fn ionice_available() -> bool {
+    static AVAILABLE: OnceLock<bool> = OnceLock::new();
+    *AVAILABLE.get_or_init(|| {
+        let found = client::tool("ionice")
+            .arg("--version")
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .status()
+            .is_ok_and(|s| s.success());
+        if !found {
+            warn!("ionice is not available; running dumps without an I/O priority");
+        }
+        found
+    })
+}
+
+/// Escapes `text` for use as a literal inside mydumper's `--regex`.
+fn regex_escape(text: &str) -> String {
+    text.chars()
+        .flat_map(|c| {
+            let escape = "\\.+*?()|[]{}^$".contains(c).then_some('\\');
+            escape.into_iter().chain([c])
+        })
+        .collect()
+}
+
+/// Dumps `db` with mydumper into the directory `output_dir`, one file per
+/// table (and per chunk), using `dump_threads` threads.
+fn run_mydumper(
+    db: &DatabaseConfig,
+    output_dir: &std::path::Path,
+    extra_args: &[String],
+) -> Result<(), BackupError> {
+    let mut c = client::tool("mydumper");
+    c.envs(&db.env)
+        .arg("--host")
+        .arg(&db.connection.host)
+        .arg("--port")
+        .arg(db.connection.port.to_string())
+        .arg("--user")
+        .arg(&db.connection.user)
+        .arg("--database")
+        .arg(&db.connection.database)
+        .arg("--outputdir")
+        .arg(output_dir);
+    // Through MYSQL_PWD rather than --password, which `ps` would show
+    client::set_password(&mut c, &db.db_type, db.connection.password.as_deref());
+    if let Some(threads) = db.dump_threads {
+        c.arg("--threads").arg(threads.to_string());
+    }
+    if db.compression == Compression::Gzip {
+        c.arg("--compress");
+    }
+    if db.protocol_compress {
+        c.arg("--compress-protocol");
+    }
+    if db.schema_only {
+        c.arg("--no-data");
+    } else if db.data_only {
+        c.arg("--no-schemas");
+    }
+    if !db.data_only {
+        if db.include_routines {
+            c.arg("--routines");
+        }
+        if db.include_events {
+            c.arg("--events");
+        }
+    }
+    if db.include_triggers {
+        c.arg("--triggers");
+    }
+    if !db.include_tables.is_empty() {
+        let tables: Vec<String> = db
+            .include_tables
+            .iter()
+            .map(|t| format!("{}.{}", db.connection.database, t))
+            .collect();
+        c.arg("--tables-list").arg(tables.join(","));
+    }
+    if !db.exclude_tables.is_empty() {
+        let tables: Vec<String> = db
+            .exclude_tables
+            .iter()
+            .map(|t| regex_escape(&format!("{}.{}", db.connection.database, t)))
+            .collect();
+        c.arg("--regex")
+            .arg(format!("^(?!({})$)", tables.join("|")));
+    }
+    if let Some(clause) = &db.where_clause {
+        c.arg("--where").arg(clause);
+    }
+    c.args(extra_args);
+
+    let mut c = with_priority(db, c);
+    debug!("Running: {}", describe_command(&c));
+    let output = c.output().map_err(|e| BackupError::spawn("mydumper", e))?;
+    if !output.status.success() {
+        return Err(BackupError::dump_failed(
+            "mydumper",
+            &db.connection.host,
+            db.connection.port,
+            &db.connection.database,
+            &output.stderr,
+        ));
+    }
+    Ok(())
+}
+
+fn sqlite3_available() -> bool {
+    static AVAILABLE: OnceLock<bool> = OnceLock::new();
+    *AVAILABLE.get_or_init(|| {
+        client::tool("sqlite3")
+            .arg("-version")
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .status()
+            .is_ok_and(|s| s.success())
+    })
+}
+
+/// Copies a SQLite database file as the backup, for when `sqlite3` isn't
+/// there to dump it. Unlike `.dump`, this doesn't see changes still in the
+/// write-ahead log, and can catch a write half-done.
+fn copy_sqlite_file(db: &DatabaseConfig, dest: &std::path::Path) -> Result<(), BackupError> {
+    let source = std::path::Path::new(&db.connection.database);
+    let wal = client::sqlite_sidecar(source, "-wal");
+    if fs::metadata(&wal).is_ok_and(|m| m.len() > 0) {
+        warn!(
+            "{:?} has uncheckpointed changes in {:?}; they are not in the copy",
+            source, wal
+        );
+    }
+    fs::copy(source, dest).map_err(|e| BackupError::write(dest, e))?;
+    Ok(())
+}
+
+fn mydumper_available() -> bool {
+    static AVAILABLE: OnceLock<bool> = OnceLock::new();
+    *AVAILABLE.get_or_init(|| {
+        client::tool("mydumper")
+            .arg("--version")
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .status()
+            .is_ok_and(|s| s.success())
+    })
+}
+
+/// `mysqldump` with connection details and common flags, but no database.
+fn mysqldump_command(db: &DatabaseConfig, skip_lock: bool, extra_args: &[String]) -> Command {
+    let mut c = client::tool("mysqldump");
+    c.envs(&db.env);
+    c.arg(format!("-h{}", db.connection.host))
+        .arg(format!("-P{}", db.connection.port))
+        .arg(format!("-u{}", db.connection.user));
+
+    client::set_password(&mut c, &db.db_type, db.connection.password.as_deref());
+
+    // Add robustness flags
+    c.arg("--column-statistics=0");
+    c.arg(format!("--default-character-set={}", db.charset()));
+    if db.protocol_compress {
+        c.arg("--compress");
+    }
+    // The date line makes every dump unique, so drop it unless asked to keep it
+    if !db.keep_dump_date {
+        c.arg("--skip-dump-date");
+    }
+    c.arg(if db.include_triggers {
+        "--triggers"
+    } else {
+        "--skip-triggers"
+    });
+
+    if skip_lock {
+        c.arg("--skip-lock-tables");
+        c.arg("--single-transaction");
+        c.arg("--quick");
+    }
+    c.args(extra_args);
+    c
+}
+
+/// Renders a command line for logging, hiding password arguments.
+/// Passwords normally travel in the environment, which is never shown.
+fn describe_command(c: &Command) -> String {
+    let program = c.get_program().to_string_lossy().to_string();
+    // For pg_dump `-p` is the port. The tool may come after a nice/ionice wrapper.
+    let is_mysql = std::iter::once(c.get_program())
+        .chain(c.get_args())
+        .any(|a| a.to_string_lossy().contains("mysql"));
+    let mut words = vec![program];
+    let mut hide_next = false;
+    for arg in c.get_args().map(|a| a.to_string_lossy().to_string()) {
+        let word = if hide_next {
+            hide_next = false;
+            "***".to_string()
+        } else if let Some((flag, _)) = arg.split_once('=').filter(|(f, _)| f.contains("password"))
+        {
+            format!("{}=***", flag)
+        } else if arg == "--password" {
+            hide_next = true;
+            arg
+        } else if is_mysql && arg.starts_with("-p") && arg.len() > 2 {
+            "-p***".to_string()
+        } else {
+            arg
+        };
+        words.push(word);
+    }
+    shell_words::join(words)
+}
+
+/// Streams an uncompressed dump of `db` to our stdout, bypassing files,
+/// deduplication and rotation entirely.
+fn dump_to_stdout(db: &DatabaseConfig, extra_args: &[String]) -> Result<()> {
+    if db.database_pattern.is_some() {
+        anyhow::bail!(
+            "{} backs up several databases (database_pattern) and can't be streamed to stdout",
+            db.name
+        );
+    }
+    let db = &db.backup_source()?;
+    for mut c in dump_commands(db, false, extra_args) {
+        debug!("Running: {}", describe_command(&c));
+        c.stdout(std::process::Stdio::inherit());
+        c.stderr(std::process::Stdio::piped());
+
+        let output = c
+            .output()
+            .with_context(|| format!("Failed to execute dump for {}", db.name))?;
+        if !output.status.success() {
+            let err_msg = String::from_utf8_lossy(&output.stderr);
+            anyhow::bail!("Dump failed for {}: {}", db.name, err_msg.trim());
+        }
+    }
+    Ok(())
+}
+
+/// How a dump's stdout is compressed on its way to disk.
+enum StreamCompression {
+    None,
+    /// Single-threaded gzip in-process
+    Gzip,
+    /// Multi-threaded gzip through `pigz` with this many threads
+    Pigz(usize),
+}
+
+fn stream_compression(db: &DatabaseConfig) -> StreamCompression {
+    match db.compression {
+        Compression::None => StreamCompression::None,
+        Compression::Gzip => match db.compression_threads {
+            Some(threads) if pigz_available() => {
+                let threads = if threads == 0 {
+                    std::thread::available_parallelism().map_or(1, |n| n.get())
+                } else {
+                    threads
+                };
+                StreamCompression::Pigz(threads)
+            }
+            Some(_) => {
+                warn!(
+                    "compression_threads is set for {} but pigz is not installed; using single-threaded gzip",
+                    db.name
+                );
+                StreamCompression::Gzip
+            }
+            None => StreamCompression::Gzip,
+        },
+    }
+}
+
+fn pigz_available() -> bool {
+    static AVAILABLE: OnceLock<bool> = OnceLock::new();
+    *AVAILABLE.get_or_init(|| {
+        client::tool("pigz")
+            .arg("--version")
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .status()
+            .is_ok_and(|s| s.success())
+    })
+}
+
+type BackupWriter = Box<dyn std::io::Write + Send>;
+
+/// Where dump output is written: straight to the file, through an in-process
+/// gzip encoder, or into a `pigz` child process whose output is copied back
+/// on a separate thread. With `split_size` the file is a set of parts.
+enum DumpSink {
+    File(BackupWriter),
+    Gzip(GzEncoder<BackupWriter>),
+    Pigz(
+        std::process::Child,
+        std::thread::JoinHandle<std::io::Result<()>>,
+    ),
+}
+
+impl DumpSink {
+    fn open(
+        output_path: &std::path::Path,
+        compression: StreamCompression,
+        split_size: Option<u64>,
+        counter: Option<Arc<AtomicU64>>,
+    ) -> Result<DumpSink> {
+        let mut output_file: BackupWriter = match split_size {
+            Some(limit) => Box::new(split::SplitWriter::new(output_path, limit)),
+            None => Box::new(fs::File::create(output_path)?),
+        };
+        if let Some(counter) = counter {
+            output_file = Box::new(progress::CountingWriter::new(output_file, counter));
+        }
+        Ok(match compression {
+            StreamCompression::None => DumpSink::File(output_file),
+            StreamCompression::Gzip => {
+                DumpSink::Gzip(GzEncoder::new(output_file, flate2::Compression::default()))
+            }
+            StreamCompression::Pigz(threads) => {
+                let mut child = client::tool("pigz")
+                    .arg("-p")
+                    .arg(threads.to_string())
+                    .stdin(std::process::Stdio::piped())
+                    .stdout(std::process::Stdio::piped())
+                    .spawn()
+                    .context("Failed to execute pigz")?;
+                let mut compressed = child
+                    .stdout
+                    .take()
+                    .context("Failed to capture pigz output")?;
+                let mut output_file = output_file;
+                let copier = std::thread::spawn(move || {
+                    std::io::copy(&mut compressed, &mut output_file)?;
+                    output_file.flush()
+                });
+                DumpSink::Pigz(child, copier)
+            }
+        })
+    }
+
+    fn writer(&mut self) -> &mut dyn std::io::Write {
+        match self {
+            DumpSink::File(f) => f,
+            DumpSink::Gzip(e) => e,
+            DumpSink::Pigz(child, _) => child.stdin.as_mut().expect("pigz stdin is piped"),
+        }
+    }
+
+    fn finish(self) -> Result<()> {
+        match self {
+            DumpSink::File(mut f) => f.flush()?,
+            DumpSink::Gzip(e) => {
+                e.finish()?.flush()?;
+            }
+            DumpSink::Pigz(mut child, copier) => {
+                drop(child.stdin.take());
+                let status = child.wait()?;
+                copier
+                    .join()
+                    .map_err(|_| anyhow::anyhow!("pigz output thread panicked"))??;
+                if !status.success() {
+                    anyhow::bail!("pigz failed with status: {}", status);
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Reads an async pipe from a blocking thread, so the dump output can go
+/// through the same `Read`-based copying as everything else.
+struct BlockingReader<R> {
+    inner: R,
+    runtime: tokio::runtime::Handle,
+}
+
+impl<R: tokio::io::AsyncRead + Unpin> Read for BlockingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        use tokio::io::AsyncReadExt;
+        self.runtime.block_on(self.inner.read(buf))
+    }
+}
+
+/// Runs dump commands in order, concatenating their stdout into
+/// `output_path` with the given compression. Stops at the first command that
+/// fails and returns its output (status and stderr), otherwise the last one's.
+/// With `timeout`, a command still running when it runs out is killed.
+async fn run_dump(
+    commands: Vec<Command>,
+    output_path: &std::path::Path,
+    compression: StreamCompression,
+    split_size: Option<u64>,
+    counter: Option<Arc<AtomicU64>>,
+    rate_limit: Option<u64>,
+    timeout: Option<u64>,
+) -> Result<std::process::Output, BackupError> {
+    // The sink reports through anyhow; recover I/O errors so a full disk is recognised
+    let sink_error = |e: anyhow::Error| match e.downcast::<std::io::Error>() {
+        Ok(io) => BackupError::write(output_path, io),
+        Err(e) => BackupError::Other(e),
+    };
+    let mut sink =
+        DumpSink::open(output_path, compression, split_size, counter).map_err(sink_error)?;
+    // One limit for the whole dump, however many commands it takes
+    let deadline = timeout.map(|secs| tokio::time::Instant::now() + Duration::from_secs(secs));
+
+    let mut last_output = None;
+    for c in commands {
+        let tool = c.get_program().to_string_lossy().to_string();
+        debug!("Running: {}", describe_command(&c));
+        let mut c = tokio::process::Command::from(c);
+        c.stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .kill_on_drop(true);
+        let mut child = c.spawn().map_err(|e| BackupError::spawn(&tool, e))?;
+        let stdout = child
+            .stdout
+            .take()
+            .context("Failed to capture dump output")?;
+        let mut stderr = child
+            .stderr
+            .take()
+            .context("Failed to capture dump errors")?;
+        let stderr = tokio::spawn(async move {
+            use tokio::io::AsyncReadExt;
+            let mut buf = Vec::new();
+            stderr.read_to_end(&mut buf).await.map(|_| buf)
+        });
+
+        let runtime = tokio::runtime::Handle::current();
+        let mut copy = tokio::task::spawn_blocking(move || {
+            let mut stdout = BlockingReader {
+                inner: stdout,
+                runtime,
+            };
+            let copied = match rate_limit {
+                Some(rate) => {
+                    std::io::copy(&mut throttle::RateLimited::new(stdout, rate), sink.writer())
+                }
+                None => std::io::copy(&mut stdout, sink.writer()),
+            };
+            (sink, copied)
+        });
+
+        let finished = async {
+            let copied = (&mut copy).await;
+            let status = child.wait().await;
+            (copied, status)
+        };
+        let (copied, status) = match deadline {
+            Some(deadline) => match tokio::time::timeout_at(deadline, finished).await {
+                Ok(finished) => finished,
+                Err(_) => {
+                    child.kill().await.ok();
+                    // Killing the tool closes its output, so the copy finishes,
+                    // unless something the tool started still holds it open
+                    tokio::time::timeout(Duration::from_secs(5), copy)
+                        .await
+                        .ok();
+                    return Err(BackupError::TimedOut {
+                        tool,
+                        secs: timeout.unwrap_or_default(),
+                    });
+                }
+            },
+            None => finished.await,
+        };
+        let (returned, copied) = copied.context("Dump output thread panicked")?;
+        sink = returned;
+        copied.map_err(|e| BackupError::write(output_path, e))?;
+        let output = std::process::Output {
+            status: status?,
+            stdout: Vec::new(),
+            stderr: stderr.await.context("Dump error reader panicked")??,
+        };
+
+        let failed = !output.status.success();
+        last_output = Some(output);
+        if failed {
+            break;
+        }
+    }
+
+    sink.finish().map_err(sink_error)?;
+    Ok(last_output.context("No dump command to run")?)
+}
+
+async fn run_mysqldump(
+    db: &DatabaseConfig,
+    output_path: &std::path::Path,
+    skip_lock: bool,
+    extra_args: &[String],
+    counter: Option<Arc<AtomicU64>>,
+    rate_limit: Option<u64>,
+) -> Result<(), BackupError> {
+    let commands = dump_commands(db, skip_lock, extra_args);
+    let output = run_dump(
+        commands,
+        output_path,
+        stream_compression(db),
+        db.split_size,
+        counter,
+        rate_limit,
+        db.timeout_secs,
+    )
+    .await?;
+
+    if !output.status.success() {
+        return Err(BackupError::dump_failed(
+            "mysqldump",
+            &db.connection.host,
+            db.connection.port,
+            &db.connection.database,
+            &output.stderr,
+        ));
+    }
+
+    Ok(())
+}
+
+/// Extension of mydumper backups, which are directories.
+const MYDUMPER_EXTENSION: &str = "mydumper";
+
+/// Extension of SQLite database files copied as they are.
+const SQLITE_EXTENSION: &str = "sqlite";
+
+/// File extension (without the leading dot) for new backups of `db`.
+fn backup_extension(db: &DatabaseConfig) -> &'static str {
+    match db.compression {
+        Compression::None => "sql",
+        Compression::Gzip => "sql.gz",
+    }
+}
+
+/// Appended to the timestamp of backups taken with `run --manual`.
+const MANUAL_SUFFIX: &str = "_manual";
 
-    save_config(&config)?;
-    println!("Configuration updated successfully!");
-    Ok(())
+/// Separates a `run --label` label from the rest of a backup's file name,
+/// e.g. `app_20240101_000000--before-migration.sql`.
+const LABEL_SEPARATOR: &str = "--";
+
+fn is_manual_backup(path: &std::path::Path) -> bool {
+    path.file_name()
+        .and_then(|n| n.to_str())
+        .and_then(strip_backup_extension)
+        .is_some_and(|stem| {
+            stem.ends_with(MANUAL_SUFFIX)
+                || stem.contains(&format!("{}{}", MANUAL_SUFFIX, LABEL_SEPARATOR))
+        })
 }
 
-async fn command_run() -> Result<()> {
-    let config = load_config()?;
-    if config.databases.is_empty() {
-        warn!("No databases configured. Run `add` command first.");
-        return Ok(());
-    }
+fn strip_backup_extension(name: &str) -> Option<&str> {
+    manifest::BACKUP_EXTENSIONS
+        .iter()
+        .find_map(|ext| name.strip_suffix(ext))
+}
 
-    for db in config.databases {
-        if let Err(e) = perform_backup(&db).await {
-            error!("Failed to backup {}: {}", db.name, e);
-        }
-    }
-    Ok(())
+fn is_backup_file_name(name: &str) -> bool {
+    manifest::BACKUP_EXTENSIONS
+        .iter()
+        .any(|ext| name.ends_with(ext))
 }
 
-async fn command_daemon() -> Result<()> {
-    info!("Starting daemon mode...");
-    let mut last_run_times: std::collections::HashMap<String, chrono::DateTime<Local>> =
-        std::collections::HashMap::new();
+fn get_last_backup(db: &DatabaseConfig) -> Option<PathBuf> {
+    list_backups(db).pop()
+}
 
-    loop {
-        sleep(Duration::from_secs(10)).await;
-        let now = Local::now();
+/// Backups belonging to `db` in its output directory, oldest first. Split
+/// backups are listed once, by their logical (unsuffixed) path.
+fn list_backups(db: &DatabaseConfig) -> Vec<PathBuf> {
+    let Ok(entries) = fs::read_dir(&db.output_dir) else {
+        return Vec::new();
+    };
+    let prefix = format!("{}_", db.name);
 
-        let config = match load_config() {
-            Ok(c) => c,
-            Err(e) => {
-                error!("Config error: {}", e);
-                continue;
-            }
-        };
+    let mut backups: Vec<(chrono::NaiveDateTime, PathBuf)> = entries
+        .filter_map(|entry| entry.ok())
+        .map(|entry| entry.path())
+        .filter(|path| path.is_file() || path.is_dir())
+        .filter_map(|path| {
+            let name = split::logical_name(path.file_name()?.to_str()?);
+            (name.starts_with(&prefix) && is_backup_file_name(name))
+                .then(|| db.output_dir.join(name))
+        })
+        .map(|path| (backup_timestamp(db, &path), path))
+        .collect();
 
-        for db in config.databases {
-            if !db.enabled {
-                continue;
-            }
-            if let Some(schedule_str) = &db.schedule {
-                if let Ok(schedule) = Schedule::from_str(schedule_str) {
-                    let search_start = now - chrono::Duration::seconds(61);
-                    if let Some(due_time) = schedule.after(&search_start).next() {
-                        if due_time <= now {
-                            let last_run = last_run_times.get(&db.name);
-                            if let Some(last) = last_run {
-                                if *last >= due_time {
-                                    continue;
-                                }
-                            }
+    backups.sort();
+    backups.dedup();
+    backups.into_iter().map(|(_, path)| path).collect()
+}
 
-                            info!("Executing scheduled backup for {}", db.name);
-                            if let Err(e) = perform_backup(&db).await {
-                                error!("Backup failed: {}", e);
-                            }
+/// When a backup was taken, parsed from its filename using the config's
+/// timestamp format, falling back to the file's modification time.
+fn backup_timestamp(db: &DatabaseConfig, path: &std::path::Path) -> chrono::NaiveDateTime {
+    let format = db.timestamp_format();
+    let parsed = path
+        .file_name()
+        .and_then(|n| n.to_str())
+        .and_then(|name| name.strip_prefix(&format!("{}_", db.name)))
+        .and_then(strip_backup_extension)
+        .map(|rest| {
+            rest.split_once(LABEL_SEPARATOR)
+                .map_or(rest, |(rest, _)| rest)
+        })
+        .map(|rest| rest.strip_suffix(MANUAL_SUFFIX).unwrap_or(rest))
+        .and_then(|stamp| {
+            parse_stamp(stamp, format).or_else(|| {
+                // Retry without what `filename_collision` appended
+                let (base, millis) = split_collision_suffix(stamp);
+                parse_stamp(base, format)
+                    .map(|t| t + chrono::Duration::milliseconds(millis.unwrap_or(0).into()))
+            })
+        });
 
-                            last_run_times.insert(db.name.clone(), due_time);
-                        }
-                    }
-                }
-            }
+    parsed.unwrap_or_else(|| {
+        let modified = split::backup_parts(path)
+            .first()
+            .map_or(Err(std::io::ErrorKind::NotFound.into()), |p| p.metadata())
+            .and_then(|m| m.modified())
+            .unwrap_or(std::time::SystemTime::UNIX_EPOCH);
+        chrono::DateTime::<Local>::from(modified).naive_local()
+    })
+}
+
+fn parse_stamp(stamp: &str, format: &str) -> Option<chrono::NaiveDateTime> {
+    chrono::NaiveDateTime::parse_from_str(stamp, format)
+        .ok()
+        .or_else(|| {
+            // Date-only formats carry no time of day
+            chrono::NaiveDate::parse_from_str(stamp, format)
+                .ok()
+                .and_then(|d| d.and_hms_opt(0, 0, 0))
+        })
+}
+
+/// Splits a `_N` counter and `.mmm` milliseconds off a backup's timestamp,
+/// returning the timestamp and the milliseconds.
+fn split_collision_suffix(stamp: &str) -> (&str, Option<u32>) {
+    let all_digits = |s: &str| !s.is_empty() && s.bytes().all(|b| b.is_ascii_digit());
+    let mut rest = stamp;
+    if let Some((base, counter)) = rest.rsplit_once('_') {
+        if all_digits(counter) {
+            rest = base;
+        }
+    }
+    match rest.rsplit_once('.') {
+        Some((base, millis)) if millis.len() == 3 && all_digits(millis) => {
+            (base, millis.parse().ok())
         }
+        _ => (rest, None),
     }
 }
 
-async fn perform_backup(db: &DatabaseConfig) -> Result<()> {
-    info!("Backing up database: {}", db.name);
+/// The binlog coordinates `--master-data=2` wrote as a comment near the top
+/// of a dump, ahead of any table data.
+fn read_binlog_position(path: &std::path::Path) -> Result<Option<BinlogPosition>> {
+    use std::io::BufRead;
 
-    if !db.output_dir.exists() {
-        fs::create_dir_all(&db.output_dir)?;
+    let reader = std::io::BufReader::new(client::open_backup(path)?);
+    for line in reader.split(b'\n').take(100) {
+        let line = line?;
+        if let Some(position) = BinlogPosition::parse(&String::from_utf8_lossy(&line)) {
+            return Ok(Some(position));
+        }
     }
+    Ok(None)
+}
+
+/// Whether two dumps hold the same data, ignoring the lines that differ
+/// between runs of the dump tool even when nothing changed.
+fn dumps_match(p1: &std::path::Path, p2: &std::path::Path) -> Result<bool> {
+    Ok(logical_hash(p1)? == logical_hash(p2)?)
+}
 
-    let timestamp = Local::now().format("%Y%m%d_%H%M%S");
-    let filename = format!("{}_{}.sql", db.name, timestamp);
-    let output_path = db.output_dir.join(&filename);
+/// Lines a dump tool writes differently on every run: mysqldump's date line
+/// (with `keep_dump_date`), pg_dump's start/end times (in verbose mode) and
+/// the random key of the `\restrict` guard newer pg_dump versions add.
+const VOLATILE_DUMP_LINES: &[&[u8]] = &[
+    b"-- Dump completed on",
+    b"-- Started on",
+    b"-- Completed on",
+    b"\\restrict ",
+    b"\\unrestrict ",
+];
 
-    match db.db_type {
-        DbType::MariaDB => {
-            // Deduplication Check: Find earlier backup
-            let last_backup = get_last_backup(db);
+/// SHA-256 of a dump's decompressed content without `VOLATILE_DUMP_LINES`,
+/// for deduplication. Manifests record `content_hash`, which only leaves out
+/// the date line.
+fn logical_hash(path: &std::path::Path) -> Result<String> {
+    hash_dump(path, VOLATILE_DUMP_LINES)
+}
 
-            // First attempt: Standard backup
-            if let Err(e) = run_mysqldump(db, &output_path, false).await {
-                warn!(
-                    "Standard backup failed for {}. Retrying with --skip-lock-tables. Error: {}",
-                    db.name, e
-                );
+/// SHA-256 (hex) of a dump's decompressed content, without the
+/// "-- Dump completed on <date>" line, as recorded in its manifest.
+fn content_hash(path: &std::path::Path) -> Result<String> {
+    hash_dump(path, &[b"-- Dump completed on"])
+}
 
-                if let Err(retry_err) = run_mysqldump(db, &output_path, true).await {
-                    error!("Retry with --skip-lock-tables also failed for {}", db.name);
-                    fs::remove_file(&output_path).ok(); // Cleanup incomplete file
-                    return Err(retry_err);
-                } else {
-                    info!("Backup succeeded with --skip-lock-tables for {}", db.name);
-                }
+/// SHA-256 (hex) of a dump's decompressed content, leaving out lines that
+/// start with any of `skip`.
+fn hash_dump(path: &std::path::Path, skip: &[&[u8]]) -> Result<String> {
+    use sha2::{Digest, Sha256};
+    use std::io::BufRead;
+
+    let mut hasher = Sha256::new();
+    let reader = std::io::BufReader::new(client::open_backup(path)?);
+    for line in reader.split(b'\n') {
+        let line = line?;
+        if !skip.iter().any(|l| line.starts_with(l)) {
+            hasher.update(&line);
+            hasher.update(b"\n");
+        }
+    }
+    Ok(format!("{:x}", hasher.finalize()))
+}
+
+/// Checks a new dump at `temp_path` against `db`'s earlier backups. `None`
+/// means it matched the previous backup and was discarded; otherwise returns
+/// whether `output_path` was linked to an identical backup instead, and the
+/// content hash for the manifest.
+fn deduplicate_dump(
+    db: &DatabaseConfig,
+    temp_path: &std::path::Path,
+    output_path: &std::path::Path,
+) -> Option<(bool, Option<String>)> {
+    let mut linked = false;
+    if let Some(last_path) = get_last_backup(db) {
+        if let Ok(true) = dumps_match(temp_path, &last_path) {
+            if db.dedup_storage != DedupStorage::Delete {
+                linked = link_identical(db, &last_path, output_path);
+            }
+            if !linked {
+                success!("Backup skipped (Identical to previous): {}", db.name);
+                split::remove(temp_path).ok();
+                return None;
             }
+        }
+    }
 
-            // Check for deduplication
-            if let Some(last_path) = last_backup {
-                if let Ok(true) = files_are_identical(&output_path, &last_path) {
-                    info!("Backup skipped (Identical to previous): {}", db.name);
-                    fs::remove_file(&output_path).ok();
-                    return Ok(());
+    // A linked entry holds the earlier file, whose volatile lines may differ
+    let hashed = if linked { output_path } else { temp_path };
+    match content_hash(hashed) {
+        Ok(hash) => {
+            // The data may have gone back to an older state. The new entry
+            // is still needed so the latest backup matches the database,
+            // but it can share the older file's storage.
+            if !linked {
+                if let Some(older) = find_identical_backup(db, &hash) {
+                    linked = link_identical(db, &older, output_path);
                 }
             }
+            Some((linked, Some(hash)))
         }
-        DbType::PostgreSQL => {
-            let mut c = Command::new("pg_dump");
-            c.env("PGHOST", &db.connection.host)
-                .env("PGPORT", db.connection.port.to_string())
-                .env("PGUSER", &db.connection.user)
-                .env("PGDATABASE", &db.connection.database);
-            if let Some(pass) = &db.connection.password {
-                c.env("PGPASSWORD", pass);
-            }
+        Err(e) => {
+            warn!("Could not hash backup for {}: {:#}", db.name, e);
+            Some((linked, None))
+        }
+    }
+}
 
-            let output_file = fs::File::create(&output_path)?;
-            c.stdout(output_file);
+/// Creates the new backup entry `to` as a link to the identical backup `from`,
+/// reflinked when configured and hard-linked otherwise. Returns false, after
+/// logging why, if the filesystem can't do it.
+fn link_identical(db: &DatabaseConfig, from: &std::path::Path, to: &std::path::Path) -> bool {
+    let reflink = db.dedup_storage == DedupStorage::Reflink;
+    match split::link(from, to, reflink) {
+        Ok(()) => {
+            success!(
+                "Backup for {} is identical to {:?}; {} instead of storing a copy",
+                db.name,
+                from,
+                if reflink { "reflinked" } else { "hard-linked" }
+            );
+            true
+        }
+        Err(e) => {
+            warn!("Could not link {:?} to {:?}: {:#}", to, from, e);
+            false
+        }
+    }
+}
 
-            let status = c.status().context("Failed to execute pg_dump")?;
-            if !status.success() {
-                fs::remove_file(&output_path).ok();
-                anyhow::bail!("pg_dump failed with status: {}", status);
-            }
+/// An earlier backup with the given content hash, among the `dedup_history`
+/// most recent. The newest is left out since it is compared directly.
+fn find_identical_backup(db: &DatabaseConfig, hash: &str) -> Option<PathBuf> {
+    let suffix = format!(".{}", backup_extension(db));
+    list_backups(db)
+        .into_iter()
+        .rev()
+        .skip(1)
+        .take(db.dedup_history.saturating_sub(1))
+        .filter(|path| path.to_string_lossy().ends_with(&suffix))
+        .find(|path| {
+            Manifest::read(path)
+                .ok()
+                .and_then(|m| m.content_hash)
+                .is_some_and(|h| h == hash)
+        })
+}
+
+/// Copies a finished backup (every part plus its manifest) to remote storage,
+/// checking that each object arrived with the same size.
+fn upload_backup(db: &DatabaseConfig, remote: &RemoteConfig, path: &std::path::Path) -> Result<()> {
+    if path.is_dir() {
+        warn!(
+            "Not uploading {:?}: directory backups aren't supported for remote storage",
+            path
+        );
+        return Ok(());
+    }
+    let store = remote::store(remote);
+    let name = path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .context("Backup path has no file name")?;
+    let backup_key = remote::key(remote, &name);
+    let manifest_path = manifest::manifest_path(path);
+
+    let hash = if remote.deduplicate {
+        match Manifest::read(path).ok().and_then(|m| m.content_hash) {
+            Some(hash) => Some(hash),
+            None => Some(content_hash(path)?),
+        }
+    } else {
+        None
+    };
+    if let Some(hash) = &hash {
+        if let Some(previous) = identical_upload(db, store.as_ref(), hash) {
+            // Point the manifest at the data already stored instead of uploading it again
+            let mut manifest = Manifest::read(path)?;
+            manifest.remote_data_key = Some(previous.clone());
+            manifest.write(path)?;
+            upload_verified(
+                store.as_ref(),
+                &manifest_path,
+                &format!("{}.json", backup_key),
+            )?;
+            success!(
+                "{:?} is unchanged since {}; uploaded only its manifest to {}",
+                path,
+                previous,
+                remote.bucket
+            );
+            return Ok(());
         }
     }
 
-    info!("Backup created at: {:?}", output_path);
+    let mut files = split::backup_parts(path);
+    if manifest_path.exists() {
+        files.push(manifest_path);
+    }
+    for file in &files {
+        let name = file
+            .file_name()
+            .map(|n| n.to_string_lossy().to_string())
+            .context("Backup path has no file name")?;
+        upload_verified(store.as_ref(), file, &remote::key(remote, &name))?;
+    }
+    success!("Uploaded {:?} to {}", path, remote.bucket);
 
-    rotate_backups(db)?;
+    if let Some(hash) = hash {
+        update_state(|state| {
+            state.entry(&db.name).last_upload = Some(state::RemoteUpload {
+                key: backup_key,
+                content_hash: hash,
+            });
+        })?;
+    }
+    Ok(())
+}
 
+/// Uploads `file` and checks the stored object has the same size.
+fn upload_verified(
+    store: &dyn remote::RemoteStore,
+    file: &std::path::Path,
+    key: &str,
+) -> Result<()> {
+    store.upload(file, key)?;
+    let local_size = fs::metadata(file)?.len();
+    let remote_size = store.size(key)?;
+    if local_size != remote_size {
+        anyhow::bail!(
+            "{} is {} bytes remotely but {} bytes locally",
+            key,
+            remote_size,
+            local_size
+        );
+    }
     Ok(())
 }
 
-async fn run_mysqldump(
+/// Key of the last uploaded backup of `db` if its content hash is `hash` and
+/// its data is still in remote storage.
+fn identical_upload(
     db: &DatabaseConfig,
-    output_path: &std::path::Path,
-    skip_lock: bool,
-) -> Result<()> {
-    let mut c = Command::new("mysqldump");
-    c.arg(format!("-h{}", db.connection.host))
-        .arg(format!("-P{}", db.connection.port))
-        .arg(format!("-u{}", db.connection.user));
+    store: &dyn remote::RemoteStore,
+    hash: &str,
+) -> Option<String> {
+    let last = load_state()
+        .ok()?
+        .databases
+        .get(&db.name)?
+        .last_upload
+        .clone()?;
+    if last.content_hash != hash {
+        return None;
+    }
+    match store.list(&last.key) {
+        Ok(keys) if keys.iter().any(|k| !k.ends_with(".json")) => Some(last.key),
+        Ok(_) => None,
+        Err(e) => {
+            warn!(
+                "Could not check for {} in remote storage: {:#}",
+                last.key, e
+            );
+            None
+        }
+    }
+}
 
-    if let Some(pass) = &db.connection.password {
-        c.env("MYSQL_PWD", pass);
+/// Applies `remote.retention_count` and `remote.retention_days` under the
+/// database's `retention_policy`, treating each backup's parts and manifest
+/// as one unit. Returns the keys it deleted.
+fn rotate_remote(db: &DatabaseConfig, remote: &RemoteConfig) -> Result<Vec<String>> {
+    if remote.retention_count.is_none() && remote.retention_days.is_none() {
+        return Ok(Vec::new());
     }
+    let cutoff = remote
+        .retention_days
+        .and_then(|days| i64::try_from(days).ok())
+        .and_then(chrono::Duration::try_days)
+        .map(|age| Local::now().naive_local() - age);
+    let store = remote::store(remote);
+    let keys = store.list(&remote::key(remote, &format!("{}_", db.name)))?;
 
-    // Add robustness flags
-    c.arg("--column-statistics=0");
-    c.arg("--skip-dump-date");
+    let mut groups: std::collections::BTreeMap<String, Vec<String>> = Default::default();
+    for key in keys {
+        let logical = key
+            .strip_suffix(".json")
+            .unwrap_or(split::logical_name(&key));
+        groups.entry(logical.to_string()).or_default().push(key);
+    }
+    let mut backups: Vec<(chrono::NaiveDateTime, Vec<String>)> = groups
+        .into_iter()
+        .filter(|(logical, _)| is_backup_file_name(logical))
+        .map(|(logical, keys)| {
+            let name = logical.rsplit('/').next().unwrap_or(&logical);
+            (backup_timestamp(db, std::path::Path::new(name)), keys)
+        })
+        .collect();
+    backups.sort();
 
-    if skip_lock {
-        c.arg("--skip-lock-tables");
-        c.arg("--single-transaction");
-        c.arg("--quick");
+    // Both limits delete from the oldest end, so whatever goes is a prefix.
+    // As locally, the newest backup is never removed for age alone.
+    let count = backups.len();
+    let excess = backups
+        .iter()
+        .enumerate()
+        .take_while(|(i, (taken, _))| {
+            let over_count = remote.retention_count.map(|keep| count - i > keep);
+            let old = cutoff.map(|cutoff| *taken < cutoff);
+            match db.retention_policy {
+                RetentionPolicy::Either => {
+                    over_count == Some(true) || (old == Some(true) && i + 1 < count)
+                }
+                RetentionPolicy::Both => over_count != Some(false) && old != Some(false),
+            }
+        })
+        .count();
+
+    let mut removed = Vec::new();
+    // A manifest-only upload points at the newest earlier backup that has
+    // data, so that one stays as long as the oldest kept backup needs it
+    let is_pointer = |keys: &Vec<String>| keys.iter().all(|k| k.ends_with(".json"));
+    let referenced = match backups.get(excess) {
+        Some((_, keys)) if is_pointer(keys) => backups[..excess]
+            .iter()
+            .rposition(|(_, keys)| !is_pointer(keys)),
+        _ => None,
+    };
+    for (i, (_, keys)) in backups.into_iter().take(excess).enumerate() {
+        if Some(i) == referenced {
+            continue;
+        }
+        for key in keys {
+            success!("Rotating remote backup: Removing {}", key);
+            store.delete(&key)?;
+            removed.push(key);
+        }
     }
+    Ok(removed)
+}
 
-    c.arg(&db.connection.database);
+/// What a `rotate_backups` pass deleted.
+#[derive(Debug, Default)]
+struct Rotation {
+    removed: Vec<PathBuf>,
+    /// Disk space given back, counting only files nothing else links to
+    bytes_freed: u64,
+}
 
-    let output_file = fs::File::create(output_path)?;
-    c.stdout(output_file);
-    c.stderr(std::process::Stdio::piped());
+/// Applies retention to `db`'s backups, returning the ones it deleted.
+fn rotate_backups(db: &DatabaseConfig) -> Result<Rotation> {
+    // Manual and scheduled backups are rotated independently so routine
+    // backups never evict deliberate snapshots
+    let (manual, scheduled): (Vec<PathBuf>, Vec<PathBuf>) = list_backups(db)
+        .into_iter()
+        .partition(|p| is_manual_backup(p));
 
-    let output = c.output().context("Failed to execute mysqldump")?;
+    let cutoff = db
+        .retention_age()?
+        .map(|age| Local::now().naive_local() - age);
 
-    if !output.status.success() {
-        let err_msg = String::from_utf8_lossy(&output.stderr);
-        anyhow::bail!("mysqldump failed: {}", err_msg.trim());
+    let mut rotation = Rotation::default();
+    remove_oldest(db, &scheduled, db.retention_count, cutoff, &mut rotation)?;
+    remove_oldest(
+        db,
+        &manual,
+        db.manual_retention_count.unwrap_or(db.retention_count),
+        cutoff,
+        &mut rotation,
+    )?;
+
+    if db.storage_layout == StorageLayout::ContentAddressed {
+        for (blob, size) in blobs::collect_garbage(&db.blob_dir())? {
+            success!("Removing unreferenced blob {:?}", blob);
+            rotation.bytes_freed += size;
+        }
     }
 
-    Ok(())
+    Ok(rotation)
 }
 
-fn get_last_backup(db: &DatabaseConfig) -> Option<PathBuf> {
-    let mut backups: Vec<PathBuf> = fs::read_dir(&db.output_dir)
-        .ok()?
-        .filter_map(|entry| entry.ok())
-        .map(|entry| entry.path())
-        .filter(|path| {
-            if let Some(name) = path.file_name().and_then(|n| n.to_str()) {
-                name.starts_with(&format!("{}_", db.name)) && name.ends_with(".sql")
-            } else {
-                false
+/// Bytes deleting the backup at `path` gives back. Parts that are hard-linked
+/// elsewhere (deduplicated or content-addressed backups) free nothing.
+fn reclaimable_size(path: &std::path::Path) -> u64 {
+    split::backup_parts(path)
+        .iter()
+        .filter_map(|part| part.metadata().ok())
+        .filter(|metadata| {
+            #[cfg(unix)]
+            {
+                use std::os::unix::fs::MetadataExt;
+                metadata.nlink() == 1
+            }
+            #[cfg(not(unix))]
+            {
+                let _ = metadata;
+                true
             }
         })
-        .collect();
+        .map(|metadata| metadata.len())
+        .sum()
+}
 
-    backups.sort();
-    backups.pop()
+/// Runs a user-configured hook through the shell with `env` added to its
+/// environment, failing if it exits non-zero. What it prints is logged.
+fn run_hook(command: &str, env: &[(&str, String)]) -> Result<()> {
+    let mut c = if cfg!(windows) {
+        let mut c = Command::new("cmd");
+        c.arg("/C");
+        c
+    } else {
+        let mut c = Command::new("sh");
+        c.arg("-c");
+        c
+    };
+    c.arg(command)
+        .envs(env.iter().map(|(k, v)| (k, v)))
+        .stdin(std::process::Stdio::null());
+    let output = c
+        .output()
+        .with_context(|| format!("Failed to run hook `{}`", command))?;
+    for line in String::from_utf8_lossy(&output.stdout).lines() {
+        info!("[hook] {}", line);
+    }
+    if !output.status.success() {
+        anyhow::bail!(
+            "`{}` exited with {}: {}",
+            command,
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    for line in String::from_utf8_lossy(&output.stderr).lines() {
+        warn!("[hook] {}", line);
+    }
+    Ok(())
 }
 
-fn files_are_identical(p1: &std::path::Path, p2: &std::path::Path) -> Result<bool> {
-    let f1 = fs::read(p1)?;
-    let f2 = fs::read(p2)?;
-    Ok(f1 == f2)
+/// Deletes all but the newest `keep` of `backups` (which are oldest first),
+/// plus any taken before `cutoff`. The newest backup is never removed for age.
+/// Under `RetentionPolicy::Both`, only backups beyond `keep` that were also
+/// taken before `cutoff` are deleted.
+fn remove_oldest(
+    db: &DatabaseConfig,
+    backups: &[PathBuf],
+    keep: usize,
+    cutoff: Option<chrono::NaiveDateTime>,
+    rotation: &mut Rotation,
+) -> Result<()> {
+    for (i, path) in backups.iter().enumerate() {
+        let over_count = backups.len() - i > keep;
+        let old = cutoff.is_some_and(|cutoff| backup_timestamp(db, path) < cutoff);
+        let remove = match db.retention_policy {
+            RetentionPolicy::Either => over_count || (old && i + 1 < backups.len()),
+            RetentionPolicy::Both => over_count && (old || cutoff.is_none()),
+        };
+        if remove {
+            success!("Rotating backup: Removing {:?}", path);
+            let size = reclaimable_size(path);
+            split::remove(path)?;
+            fs::remove_file(manifest::manifest_path(path)).ok();
+            fs::remove_file(manifest::server_config_path(path)).ok();
+            fs::remove_file(manifest::checksum_path(path)).ok();
+            rotation.removed.push(path.clone());
+            rotation.bytes_freed += size;
+        }
+    }
+    Ok(())
 }
 
-fn rotate_backups(db: &DatabaseConfig) -> Result<()> {
-    let mut backups: Vec<PathBuf> = fs::read_dir(&db.output_dir)?
-        .filter_map(|entry| entry.ok())
-        .map(|entry| entry.path())
-        .filter(|path| path.is_file() && path.extension().map_or(false, |ext| ext == "sql"))
-        .filter(|path| {
-            path.file_name()
-                .map_or(false, |name| name.to_string_lossy().starts_with(&db.name))
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use std::os::unix::fs::PermissionsExt;
+    use std::path::Path;
+
+    /// Stub `mysqldump`: behaviour is picked per config through `env`
+    /// (`STUB_MODE`), so tests can run in parallel against one binary.
+    const STUB_DUMP: &str = r#"#!/bin/sh
+case "$STUB_MODE" in
+  fail) echo "stub: dump failed" >&2; exit 2 ;;
+  lock) case "$*" in *--skip-lock-tables*) ;; *) echo "stub: lock wait timeout" >&2; exit 2 ;; esac ;;
+  partial) case "$*" in *--skip-lock-tables*) ;; *)
+    echo "-- output from an attempt that fails partway through"
+    echo "stub: lock wait timeout" >&2; exit 2 ;; esac ;;
+esac
+echo "-- stub dump ${STUB_CONTENT:-fixed}"
+"#;
+
+    /// Points the tool and state lookups at a scratch directory, once per process.
+    fn setup() -> &'static Path {
+        static DIR: OnceLock<tempfile::TempDir> = OnceLock::new();
+        DIR.get_or_init(|| {
+            let dir = tempfile::tempdir().unwrap();
+            let stub = dir.path().join("mysqldump");
+            fs::write(&stub, STUB_DUMP).unwrap();
+            fs::set_permissions(&stub, fs::Permissions::from_mode(0o755)).unwrap();
+            std::env::set_var("DBR_MYSQLDUMP_PATH", &stub);
+            let stub = dir.path().join("sqlite3");
+            fs::write(&stub, "#!/bin/sh\necho '-- stub sqlite dump'\n").unwrap();
+            fs::set_permissions(&stub, fs::Permissions::from_mode(0o755)).unwrap();
+            std::env::set_var("DBR_SQLITE3_PATH", &stub);
+            CONFIG_PATH.set(dir.path().join("config.toml")).ok();
+            dir
         })
-        .collect();
+        .path()
+    }
 
-    backups.sort_by_key(|path| {
-        path.metadata()
-            .and_then(|m| m.modified())
-            .unwrap_or(std::time::SystemTime::UNIX_EPOCH)
-    });
+    fn test_db(output_dir: &Path, mode: &str) -> DatabaseConfig {
+        setup();
+        let mut db = DatabaseConfig::new(
+            "app".to_string(),
+            DbType::MariaDB,
+            ConnectionDetails {
+                host: "localhost".to_string(),
+                port: 3306,
+                user: "root".to_string(),
+                password: None,
+                database: "app".to_string(),
+            },
+            output_dir.to_path_buf(),
+            2,
+            None,
+        );
+        db.env.insert("STUB_MODE".to_string(), mode.to_string());
+        db
+    }
 
-    if backups.len() > db.retention_count {
-        let to_remove = backups.len() - db.retention_count;
-        for path in backups.iter().take(to_remove) {
-            info!("Rotating backup: Removing {:?}", path);
-            fs::remove_file(path)?;
+    fn app_config(enabled: &[(&str, bool)]) -> AppConfig {
+        let mut config = AppConfig::default();
+        for (name, on) in enabled {
+            let mut db = test_db(Path::new("/nonexistent"), "ok");
+            db.name = name.to_string();
+            db.enabled = *on;
+            config.databases.push(db);
         }
+        config
     }
 
-    Ok(())
+    fn touch(dir: &Path, name: &str) -> PathBuf {
+        let path = dir.join(name);
+        fs::write(&path, name).unwrap();
+        path
+    }
+
+    fn file_names(dir: &Path) -> Vec<String> {
+        let mut names: Vec<String> = fs::read_dir(dir)
+            .unwrap()
+            .map(|e| e.unwrap().file_name().to_string_lossy().to_string())
+            .collect();
+        names.sort();
+        names
+    }
+
+    #[test]
+    fn rotation_keeps_newest_retention_count() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = test_db(dir.path(), "ok");
+        touch(dir.path(), "app_20240103_000000.sql");
+        touch(dir.path(), "app_20240101_000000.sql");
+        touch(dir.path(), "app_20240102_000000.sql");
+        touch(dir.path(), "app_20240101_000000.sql.json");
+
+        let removed = rotate_backups(&db).unwrap().removed;
+
+        assert_eq!(removed, vec![dir.path().join("app_20240101_000000.sql")]);
+        assert_eq!(
+            file_names(dir.path()),
+            vec!["app_20240102_000000.sql", "app_20240103_000000.sql"]
+        );
+    }
+
+    #[test]
+    fn rotation_counts_manual_backups_separately() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut db = test_db(dir.path(), "ok");
+        db.retention_count = 1;
+        db.manual_retention_count = Some(2);
+        touch(dir.path(), "app_20240101_000000_manual.sql");
+        touch(dir.path(), "app_20240102_000000_manual.sql");
+        touch(dir.path(), "app_20240103_000000_manual.sql");
+        touch(dir.path(), "app_20240104_000000.sql");
+        touch(dir.path(), "app_20240105_000000.sql");
+
+        rotate_backups(&db).unwrap();
+
+        assert_eq!(
+            file_names(dir.path()),
+            vec![
+                "app_20240102_000000_manual.sql",
+                "app_20240103_000000_manual.sql",
+                "app_20240105_000000.sql",
+            ]
+        );
+    }
+
+    #[test]
+    fn rotation_ignores_unrelated_files() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut db = test_db(dir.path(), "ok");
+        db.retention_count = 1;
+        touch(dir.path(), "app_20240101_000000.sql");
+        touch(dir.path(), "app_20240102_000000.sql");
+        touch(dir.path(), "notes.txt");
+
+        rotate_backups(&db).unwrap();
+
+        assert_eq!(
+            file_names(dir.path()),
+            vec!["app_20240102_000000.sql", "notes.txt"]
+        );
+    }
+
+    #[test]
+    fn rotation_skips_in_progress_backups() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut db = test_db(dir.path(), "ok");
+        db.retention_count = 1;
+        touch(dir.path(), "app_20240101_000000.sql");
+        touch(dir.path(), "app_20240102_000000.sql");
+        // Another run is still writing these
+        touch(dir.path(), "app_20240103_000000.sql.tmp");
+        touch(dir.path(), "app_20240103_000001.sql.tmp.part001");
+
+        let removed = rotate_backups(&db).unwrap().removed;
+
+        assert_eq!(removed, vec![dir.path().join("app_20240101_000000.sql")]);
+        assert_eq!(
+            file_names(dir.path()),
+            vec![
+                "app_20240102_000000.sql",
+                "app_20240103_000000.sql.tmp",
+                "app_20240103_000001.sql.tmp.part001",
+            ]
+        );
+    }
+
+    #[test]
+    fn disabled_databases_are_not_selectable() {
+        let config = app_config(&[("a", true), ("b", false), ("c", true)]);
+        let names: Vec<&str> = selectable_databases(&config, false)
+            .iter()
+            .map(|db| db.name.as_str())
+            .collect();
+        assert_eq!(names, ["a", "c"]);
+    }
+
+    #[test]
+    fn disabled_databases_are_selectable_when_included() {
+        let config = app_config(&[("a", true), ("b", false)]);
+        let names: Vec<&str> = selectable_databases(&config, true)
+            .iter()
+            .map(|db| db.name.as_str())
+            .collect();
+        assert_eq!(names, ["a", "b"]);
+    }
+
+    #[test]
+    fn backup_timestamp_uses_configured_format() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut db = test_db(dir.path(), "ok");
+
+        let parsed = backup_timestamp(&db, &dir.path().join("app_20240315_142500_manual.sql.gz"));
+        assert_eq!(
+            parsed,
+            chrono::NaiveDate::from_ymd_opt(2024, 3, 15)
+                .unwrap()
+                .and_hms_opt(14, 25, 0)
+                .unwrap()
+        );
+
+        db.timestamp_format = Some("%Y-%m-%d".to_string());
+        let parsed = backup_timestamp(&db, &dir.path().join("app_2024-03-15.sql"));
+        assert_eq!(
+            parsed,
+            chrono::NaiveDate::from_ymd_opt(2024, 3, 15)
+                .unwrap()
+                .and_hms_opt(0, 0, 0)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn backup_file_names() {
+        assert!(is_backup_file_name("app_20240101_000000.sql"));
+        assert!(is_backup_file_name("app_20240101_000000.sql.gz"));
+        assert!(!is_backup_file_name("app_20240101_000000.sql.json"));
+        assert_eq!(
+            split::logical_name("app_20240101_000000.sql.part003"),
+            "app_20240101_000000.sql"
+        );
+        assert!(is_manual_backup(Path::new(
+            "app_20240101_000000_manual.sql"
+        )));
+        assert!(!is_manual_backup(Path::new("app_20240101_000000.sql")));
+    }
+
+    #[tokio::test]
+    async fn identical_backup_is_deduplicated() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut db = test_db(dir.path(), "ok");
+        // Sub-second timestamps keep file names distinct without sleeping
+        db.timestamp_format = Some("%Y%m%d_%H%M%S%.9f".to_string());
+        let opts = BackupOptions::default();
+
+        let first = perform_backup(&db, &opts).await.unwrap();
+        assert!(matches!(first, BackupOutcome::Created { .. }));
+        let second = perform_backup(&db, &opts).await.unwrap();
+        assert!(matches!(second, BackupOutcome::Deduplicated));
+        assert_eq!(list_backups(&db).len(), 1);
+
+        let forced = BackupOptions {
+            force: true,
+            ..Default::default()
+        };
+        let third = perform_backup(&db, &forced).await.unwrap();
+        assert!(matches!(third, BackupOutcome::Created { .. }));
+        assert_eq!(list_backups(&db).len(), 2);
+    }
+
+    #[tokio::test]
+    async fn since_last_skips_unchanged_sqlite_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let source = tempfile::tempdir().unwrap();
+        let file = touch(source.path(), "app.db");
+        let mut db = test_db(dir.path(), "ok");
+        db.db_type = DbType::SQLite;
+        db.connection.database = file.to_string_lossy().to_string();
+        db.since_last = true;
+        db.timestamp_format = Some("%Y%m%d_%H%M%S%.9f".to_string());
+        let opts = BackupOptions::default();
+
+        let first = perform_backup(&db, &opts).await.unwrap();
+        assert!(matches!(first, BackupOutcome::Created { .. }));
+        let second = perform_backup(&db, &opts).await.unwrap();
+        assert!(matches!(second, BackupOutcome::Deduplicated));
+
+        fs::write(&file, "app.db, changed").unwrap();
+        let third = perform_backup(&db, &opts).await.unwrap();
+        assert!(matches!(third, BackupOutcome::Created { .. }));
+        assert_eq!(list_backups(&db).len(), 2);
+    }
+
+    #[tokio::test]
+    async fn same_second_backups_get_distinct_names() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut db = test_db(dir.path(), "ok");
+        // A date-only format makes every run in the test collide
+        db.timestamp_format = Some("%Y%m%d".to_string());
+        db.retention_count = 3;
+        let forced = BackupOptions {
+            force: true,
+            ..Default::default()
+        };
+
+        perform_backup(&db, &forced).await.unwrap();
+        perform_backup(&db, &forced).await.unwrap();
+        db.filename_collision = FilenameCollision::Milliseconds;
+        perform_backup(&db, &forced).await.unwrap();
+
+        let backups = list_backups(&db);
+        assert_eq!(backups.len(), 3, "{:?}", file_names(dir.path()));
+        let stamp = Local::now().format("%Y%m%d").to_string();
+        assert!(backups.contains(&dir.path().join(format!("app_{}.sql", stamp))));
+        assert!(backups.contains(&dir.path().join(format!("app_{}_1.sql", stamp))));
+        let with_millis = backups.iter().any(|p| {
+            p.file_name()
+                .and_then(|n| n.to_str())
+                .and_then(|n| n.strip_prefix(&format!("app_{}.", stamp)))
+                .and_then(|n| n.strip_suffix(".sql"))
+                .is_some_and(|millis| millis.len() == 3)
+        });
+        assert!(with_millis, "{:?}", backups);
+    }
+
+    #[tokio::test]
+    async fn mariadb_retries_without_table_locks() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = test_db(dir.path(), "lock");
+
+        let outcome = perform_backup(&db, &BackupOptions::default())
+            .await
+            .unwrap();
+
+        assert!(matches!(outcome, BackupOutcome::Created { .. }));
+        let backups = list_backups(&db);
+        assert_eq!(backups.len(), 1);
+        let content = fs::read_to_string(&backups[0]).unwrap();
+        assert!(content.contains("stub dump"));
+    }
+
+    #[tokio::test]
+    async fn retry_replaces_partial_output() {
+        let dir = tempfile::tempdir().unwrap();
+        let mut db = test_db(dir.path(), "partial");
+        // The failed attempt writes more parts than the retry
+        db.split_size = Some(8);
+
+        perform_backup(&db, &BackupOptions::default())
+            .await
+            .unwrap();
+
+        let backups = list_backups(&db);
+        assert_eq!(backups.len(), 1);
+        let mut content = String::new();
+        split::open_raw(&backups[0])
+            .unwrap()
+            .read_to_string(&mut content)
+            .unwrap();
+        assert_eq!(content, "-- stub dump fixed\n");
+        assert!(
+            file_names(dir.path()).iter().all(|n| !n.contains(".tmp")),
+            "{:?}",
+            file_names(dir.path())
+        );
+    }
+
+    #[tokio::test]
+    async fn failed_dump_leaves_no_file() {
+        let dir = tempfile::tempdir().unwrap();
+        let db = test_db(dir.path(), "fail");
+
+        let err = perform_backup(&db, &BackupOptions::default())
+            .await
+            .unwrap_err();
+
+        assert!(matches!(err, BackupError::DumpFailed { .. }), "{}", err);
+        assert!(list_backups(&db).is_empty());
+    }
+
+    /// Value of `var` in a command's environment; `None` if it isn't set.
+    fn command_env(c: &Command, var: &str) -> Option<String> {
+        c.get_envs()
+            .find(|(k, _)| *k == var)
+            .and_then(|(_, v)| v)
+            .map(|v| v.to_string_lossy().to_string())
+    }
+
+    #[test]
+    fn empty_password_is_passed_but_missing_one_is_not() {
+        let mut db = test_db(Path::new("/nonexistent"), "ok");
+        assert_eq!(
+            command_env(&mysqldump_command(&db, false, &[]), "MYSQL_PWD"),
+            None
+        );
+        db.connection.password = Some(String::new());
+        assert_eq!(
+            command_env(&mysqldump_command(&db, false, &[]), "MYSQL_PWD"),
+            Some(String::new())
+        );
+
+        let mut conn = db.connection.clone();
+        conn.password = None;
+        let psql = client::client_command(&DbType::PostgreSQL, &conn, "app");
+        assert_eq!(command_env(&psql, "PGPASSWORD"), None);
+        conn.password = Some(String::new());
+        let psql = client::client_command(&DbType::PostgreSQL, &conn, "app");
+        assert_eq!(command_env(&psql, "PGPASSWORD"), Some(String::new()));
+    }
+
+    #[test]
+    fn configs_in_one_directory_get_separate_state_files() {
+        let prod = Path::new("/etc/dbr/prod.toml");
+        let staging = Path::new("/etc/dbr/staging.yaml");
+        for name in ["state.json", "daemon.pid", "daemon-status.json", "locks"] {
+            let a = prod.with_file_name(profile_file_name(prod, name));
+            let b = staging.with_file_name(profile_file_name(staging, name));
+            assert_ne!(a, b, "{} is shared", name);
+        }
+        assert_eq!(profile_file_name(prod, "daemon.pid"), "prod.daemon.pid");
+
+        // The default config keeps the names existing installs already use
+        let default = Path::new("/home/u/.config/db-backup-rs/config.toml");
+        assert_eq!(profile_file_name(default, "state.json"), "state.json");
+    }
 }
@@ -0,0 +1,202 @@
+use crate::models::{Compression, DbType};
+use crate::split;
+use anyhow::{Context, Result};
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Metadata written next to each backup as `<backup file>.json`.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct Manifest {
+    pub config_name: String,
+    pub db_type: DbType,
+    pub database: String,
+    pub file: String,
+    pub created_at: DateTime<Local>,
+    pub size_bytes: u64,
+    #[serde(default)]
+    pub compression: Compression,
+    #[serde(default)]
+    pub restore_check: Option<RestoreCheck>,
+    /// SHA-256 of the decompressed dump, ignoring mysqldump's date line
+    #[serde(default)]
+    pub content_hash: Option<String>,
+    /// Where the server's binlog stood when the dump's snapshot was taken
+    #[serde(default)]
+    pub binlog_position: Option<BinlogPosition>,
+    /// Set when only this manifest was uploaded because the data matched an
+    /// earlier upload: the remote key holding that data
+    #[serde(default)]
+    pub remote_data_key: Option<String>,
+    /// For SQLite, the database file as it was when backed up
+    #[serde(default)]
+    pub source_file: Option<SourceFile>,
+}
+
+/// Size and modification time of a SQLite database file, together with its
+/// write-ahead log, for telling whether it changed without reading it.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct SourceFile {
+    pub size: u64,
+    pub modified: DateTime<Local>,
+}
+
+impl SourceFile {
+    pub fn read(database: &Path) -> Result<SourceFile> {
+        let meta = fs::metadata(database)?;
+        let mut size = meta.len();
+        let mut modified = meta.modified()?;
+        if let Ok(wal) = fs::metadata(crate::client::sqlite_sidecar(database, "-wal")) {
+            size += wal.len();
+            modified = modified.max(wal.modified()?);
+        }
+        Ok(SourceFile {
+            size,
+            modified: modified.into(),
+        })
+    }
+}
+
+/// Binlog coordinates from the `CHANGE MASTER TO` comment `--master-data=2` writes.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct BinlogPosition {
+    pub file: String,
+    pub position: u64,
+}
+
+impl BinlogPosition {
+    /// Parses a line like
+    /// `-- CHANGE MASTER TO MASTER_LOG_FILE='mysql-bin.000003', MASTER_LOG_POS=154;`
+    pub fn parse(line: &str) -> Option<BinlogPosition> {
+        let rest = line
+            .trim_start_matches("-- ")
+            .strip_prefix("CHANGE MASTER TO ")?;
+        let file = rest
+            .split_once("MASTER_LOG_FILE='")?
+            .1
+            .split_once('\'')?
+            .0
+            .to_string();
+        let position = rest
+            .split_once("MASTER_LOG_POS=")?
+            .1
+            .split(|c: char| !c.is_ascii_digit())
+            .next()?
+            .parse()
+            .ok()?;
+        Some(BinlogPosition { file, position })
+    }
+}
+
+impl std::fmt::Display for BinlogPosition {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.file, self.position)
+    }
+}
+
+/// Outcome of test-restoring a backup into a scratch database.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RestoreCheck {
+    pub passed: bool,
+    pub checked_at: DateTime<Local>,
+    /// Number of tables found in the scratch database after the restore.
+    pub tables: Option<u64>,
+    pub error: Option<String>,
+}
+
+pub fn manifest_path(backup_path: &Path) -> PathBuf {
+    let mut name = backup_path.as_os_str().to_os_string();
+    name.push(".json");
+    PathBuf::from(name)
+}
+
+/// Extensions a backup's file name can end in.
+pub const BACKUP_EXTENSIONS: [&str; 4] = [".sql.gz", ".sql", ".mydumper", ".sqlite"];
+
+/// Where `include_server_config` writes the server's settings for a backup:
+/// `<name>_<timestamp>.config.txt` next to it.
+pub fn server_config_path(backup_path: &Path) -> PathBuf {
+    let name = backup_path
+        .file_name()
+        .map(|n| n.to_string_lossy().to_string())
+        .unwrap_or_default();
+    let stem = BACKUP_EXTENSIONS
+        .iter()
+        .find_map(|ext| name.strip_suffix(ext))
+        .unwrap_or(&name);
+    backup_path.with_file_name(format!("{}{}", stem, SERVER_CONFIG_SUFFIX))
+}
+
+pub const SERVER_CONFIG_SUFFIX: &str = ".config.txt";
+
+/// `<backup file>.sha256`, in `sha256sum` format so `sha256sum -c` can
+/// check it from the output directory.
+pub fn checksum_path(backup_path: &Path) -> PathBuf {
+    let mut name = backup_path.as_os_str().to_os_string();
+    name.push(CHECKSUM_SUFFIX);
+    PathBuf::from(name)
+}
+
+pub const CHECKSUM_SUFFIX: &str = ".sha256";
+
+/// SHA-256 of each file making up the backup as stored, named relative to
+/// the backup's directory.
+fn checksums(backup_path: &Path) -> Result<Vec<(String, String)>> {
+    let dir = backup_path.parent().unwrap_or(Path::new(""));
+    split::backup_parts(backup_path)
+        .into_iter()
+        .map(|part| {
+            let mut hasher = Sha256::new();
+            let mut file =
+                fs::File::open(&part).with_context(|| format!("Failed to open {:?}", part))?;
+            std::io::copy(&mut file, &mut hasher)
+                .with_context(|| format!("Failed to read {:?}", part))?;
+            let name = part.strip_prefix(dir).unwrap_or(&part);
+            Ok((
+                format!("{:x}", hasher.finalize()),
+                name.to_string_lossy().to_string(),
+            ))
+        })
+        .collect()
+}
+
+pub fn write_checksum(backup_path: &Path) -> Result<()> {
+    let content: String = checksums(backup_path)?
+        .into_iter()
+        .map(|(hash, name)| format!("{}  {}\n", hash, name))
+        .collect();
+    let path = checksum_path(backup_path);
+    fs::write(&path, content).with_context(|| format!("Failed to write {:?}", path))
+}
+
+/// Whether the backup still matches its `.sha256` file; `None` when it has none.
+pub fn verify_checksum(backup_path: &Path) -> Result<Option<bool>> {
+    let path = checksum_path(backup_path);
+    let Ok(content) = fs::read_to_string(&path) else {
+        return Ok(None);
+    };
+    let expected: Vec<(String, String)> = content
+        .lines()
+        .filter_map(|line| line.split_once("  "))
+        .map(|(hash, name)| (hash.to_string(), name.to_string()))
+        .collect();
+    Ok(Some(checksums(backup_path)? == expected))
+}
+
+impl Manifest {
+    pub fn read(backup_path: &Path) -> Result<Manifest> {
+        let path = manifest_path(backup_path);
+        let content =
+            fs::read_to_string(&path).with_context(|| format!("Failed to read {:?}", path))?;
+        serde_json::from_str(&content).with_context(|| format!("Invalid manifest {:?}", path))
+    }
+
+    pub fn write(&self, backup_path: &Path) -> Result<()> {
+        let content = serde_json::to_string_pretty(self)?;
+        fs::write(manifest_path(backup_path), content)
+            .with_context(|| format!("Failed to write manifest for {:?}", backup_path))?;
+        Ok(())
+    }
+}
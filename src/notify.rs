@@ -0,0 +1,62 @@
+use crate::client;
+use anyhow::{Context, Result};
+use chrono::Local;
+use serde_json::json;
+use std::io::Write;
+use std::process::Stdio;
+
+/// How long a webhook may take before it's given up on, so a dead endpoint
+/// doesn't hold up the daemon's next backup.
+const TIMEOUT_SECS: u64 = 5;
+
+pub fn backup_failed(url: &str, name: &str, error: &str) {
+    let payload = json!({
+        "text": format!("Backup of {} failed: {}", name, error),
+        "database": name,
+        "status": "failed",
+        "error": error,
+        "timestamp": Local::now(),
+    });
+    send(url, name, &payload);
+}
+
+pub fn backup_succeeded(url: &str, name: &str, size_bytes: u64) {
+    let payload = json!({
+        "text": format!("Backup of {} succeeded ({} bytes)", name, size_bytes),
+        "database": name,
+        "status": "succeeded",
+        "size_bytes": size_bytes,
+        "timestamp": Local::now(),
+    });
+    send(url, name, &payload);
+}
+
+/// POSTs `payload` as JSON. Its `text` field is what Slack-style webhooks
+/// display; generic receivers get the other fields too. Failures are only
+/// logged: a notification never fails a backup.
+fn send(url: &str, name: &str, payload: &serde_json::Value) {
+    if let Err(e) = post(url, payload) {
+        log::warn!("Failed to send webhook notification for {}: {:#}", name, e);
+    }
+}
+
+fn post(url: &str, payload: &serde_json::Value) -> Result<()> {
+    let mut c = client::tool("curl");
+    c.args(["--silent", "--show-error", "--fail", "--max-time"])
+        .arg(TIMEOUT_SECS.to_string())
+        .args(["--header", "Content-Type: application/json"])
+        .args(["--data-binary", "@-"])
+        .arg(url)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped());
+    let mut child = c.spawn().context("Failed to execute curl")?;
+    let mut stdin = child.stdin.take().context("Failed to open curl stdin")?;
+    stdin.write_all(payload.to_string().as_bytes())?;
+    drop(stdin);
+    let output = child.wait_with_output()?;
+    if !output.status.success() {
+        anyhow::bail!("{}", String::from_utf8_lossy(&output.stderr).trim());
+    }
+    Ok(())
+}
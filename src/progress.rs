@@ -0,0 +1,111 @@
+use std::io::Write;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Arc;
+use std::thread::JoinHandle;
+use std::time::{Duration, Instant};
+
+/// A one-line percentage/ETA display on stderr for interactive runs.
+///
+/// There is no way to know how big a dump will be, so the estimate compares
+/// bytes written so far against the previous backup's size, and holds at 99%
+/// if this one turns out larger.
+pub struct Progress {
+    written: Arc<AtomicU64>,
+    done: Arc<AtomicBool>,
+    handle: Option<JoinHandle<()>>,
+}
+
+impl Progress {
+    pub fn start(name: &str, expected: Option<u64>) -> Progress {
+        let written = Arc::new(AtomicU64::new(0));
+        let done = Arc::new(AtomicBool::new(false));
+        let handle = {
+            let written = written.clone();
+            let done = done.clone();
+            let name = name.to_string();
+            std::thread::spawn(move || {
+                let started = Instant::now();
+                while !done.load(Ordering::Relaxed) {
+                    std::thread::sleep(Duration::from_millis(250));
+                    let line = render(
+                        &name,
+                        written.load(Ordering::Relaxed),
+                        expected,
+                        started.elapsed(),
+                    );
+                    eprint!("\r\x1b[2K{}", line);
+                    std::io::stderr().flush().ok();
+                }
+                eprint!("\r\x1b[2K");
+            })
+        };
+        Progress {
+            written,
+            done,
+            handle: Some(handle),
+        }
+    }
+
+    /// Shared byte counter to feed with everything written to disk.
+    pub fn counter(&self) -> Arc<AtomicU64> {
+        self.written.clone()
+    }
+}
+
+impl Drop for Progress {
+    fn drop(&mut self) {
+        self.done.store(true, Ordering::Relaxed);
+        if let Some(handle) = self.handle.take() {
+            handle.join().ok();
+        }
+    }
+}
+
+fn render(name: &str, written: u64, expected: Option<u64>, elapsed: Duration) -> String {
+    let Some(expected) = expected.filter(|e| *e > 0) else {
+        return format!("{}: {} written", name, crate::format_bytes(written));
+    };
+
+    let percent = (written.saturating_mul(100) / expected).min(99);
+    let eta = if written == 0 {
+        "--:--".to_string()
+    } else if written >= expected {
+        "finishing".to_string()
+    } else {
+        let remaining = elapsed.as_secs_f64() * (expected - written) as f64 / written as f64;
+        let secs = remaining.round() as u64;
+        format!("ETA {:02}:{:02}", secs / 60, secs % 60)
+    };
+    format!(
+        "{}: {}% ({} of ~{}) {}",
+        name,
+        percent,
+        crate::format_bytes(written),
+        crate::format_bytes(expected),
+        eta
+    )
+}
+
+/// Passes writes through while adding their size to a shared counter.
+pub struct CountingWriter<W> {
+    inner: W,
+    count: Arc<AtomicU64>,
+}
+
+impl<W> CountingWriter<W> {
+    pub fn new(inner: W, count: Arc<AtomicU64>) -> CountingWriter<W> {
+        CountingWriter { inner, count }
+    }
+}
+
+impl<W: Write> Write for CountingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.count.fetch_add(n as u64, Ordering::Relaxed);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
@@ -0,0 +1,373 @@
+use crate::client;
+use crate::models::{RemoteConfig, RemoteProvider};
+use anyhow::{Context, Result};
+use std::io::Write;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+
+/// An object store backups are copied to. Each provider shells out to its
+/// own CLI, which picks up credentials the standard way for that cloud
+/// (environment, config files or the metadata service).
+pub trait RemoteStore {
+    fn upload(&self, local: &Path, key: &str) -> Result<()>;
+    /// Keys under `prefix`
+    fn list(&self, prefix: &str) -> Result<Vec<String>>;
+    fn delete(&self, key: &str) -> Result<()>;
+    /// Size of a stored object in bytes
+    fn size(&self, key: &str) -> Result<u64>;
+}
+
+pub fn store(config: &RemoteConfig) -> Box<dyn RemoteStore> {
+    match config.provider {
+        RemoteProvider::Gcs => Box::new(Gcs {
+            bucket: config.bucket.clone(),
+        }),
+        RemoteProvider::Azure => Box::new(Azure {
+            container: config.bucket.clone(),
+            account: config.account.clone(),
+        }),
+        RemoteProvider::Sftp => Box::new(Sftp {
+            host: config.host.clone().unwrap_or_default(),
+            port: config.port,
+            user: config.user.clone(),
+            key_path: config.key_path.clone(),
+            dir: config.bucket.trim_end_matches('/').to_string(),
+        }),
+        RemoteProvider::S3 => Box::new(S3 {
+            bucket: config.bucket.clone(),
+            endpoint: config.endpoint.clone(),
+            region: config.region.clone(),
+            credentials: config
+                .access_key_id
+                .clone()
+                .zip(config.secret_access_key.clone()),
+        }),
+    }
+}
+
+/// Object key for a local file name, under the configured prefix.
+pub fn key(config: &RemoteConfig, file_name: &str) -> String {
+    let prefix = config.prefix.trim_matches('/');
+    if prefix.is_empty() {
+        file_name.to_string()
+    } else {
+        format!("{}/{}", prefix, file_name)
+    }
+}
+
+fn run(mut c: Command, what: &str) -> Result<String> {
+    let program = c.get_program().to_string_lossy().to_string();
+    let output = c
+        .output()
+        .with_context(|| format!("Failed to execute {}", program))?;
+    if !output.status.success() {
+        let err_msg = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!("{} failed: {}", what, err_msg.trim());
+    }
+    Ok(String::from_utf8_lossy(&output.stdout).trim().to_string())
+}
+
+struct Gcs {
+    bucket: String,
+}
+
+impl Gcs {
+    fn url(&self, key: &str) -> String {
+        format!("gs://{}/{}", self.bucket, key)
+    }
+}
+
+impl RemoteStore for Gcs {
+    fn upload(&self, local: &Path, key: &str) -> Result<()> {
+        let mut c = client::tool("gcloud");
+        c.args(["storage", "cp", "--quiet"])
+            .arg(local)
+            .arg(self.url(key));
+        run(c, "GCS upload").map(|_| ())
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<String>> {
+        let mut c = client::tool("gcloud");
+        c.args(["storage", "ls"])
+            .arg(format!("{}*", self.url(prefix)));
+        let root = self.url("");
+        match run(c, "GCS list") {
+            Ok(out) => Ok(out
+                .lines()
+                .filter_map(|l| l.trim().strip_prefix(&root))
+                .map(String::from)
+                .collect()),
+            // `ls` fails when nothing matches
+            Err(e) if e.to_string().contains("matched no objects") => Ok(Vec::new()),
+            Err(e) => Err(e),
+        }
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        let mut c = client::tool("gcloud");
+        c.args(["storage", "rm", "--quiet"]).arg(self.url(key));
+        run(c, "GCS delete").map(|_| ())
+    }
+
+    fn size(&self, key: &str) -> Result<u64> {
+        let mut c = client::tool("gcloud");
+        c.args(["storage", "objects", "describe"])
+            .arg(self.url(key))
+            .arg("--format=value(size)");
+        Ok(run(c, "GCS describe")?.parse()?)
+    }
+}
+
+struct Azure {
+    container: String,
+    account: Option<String>,
+}
+
+impl Azure {
+    fn command(&self, action: &str) -> Command {
+        let mut c = client::tool("az");
+        c.args(["storage", "blob", action, "--only-show-errors"])
+            .arg("--container-name")
+            .arg(&self.container);
+        if let Some(account) = &self.account {
+            c.arg("--account-name").arg(account);
+        }
+        // Without a key, SAS token or connection string, use the signed-in identity
+        let has_secret = [
+            "AZURE_STORAGE_CONNECTION_STRING",
+            "AZURE_STORAGE_KEY",
+            "AZURE_STORAGE_SAS_TOKEN",
+        ]
+        .iter()
+        .any(|v| std::env::var_os(v).is_some());
+        if !has_secret {
+            c.arg("--auth-mode").arg("login");
+        }
+        c
+    }
+}
+
+impl RemoteStore for Azure {
+    fn upload(&self, local: &Path, key: &str) -> Result<()> {
+        let mut c = self.command("upload");
+        c.arg("--name")
+            .arg(key)
+            .arg("--file")
+            .arg(local)
+            .arg("--overwrite");
+        run(c, "Azure upload").map(|_| ())
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<String>> {
+        let mut c = self.command("list");
+        c.arg("--prefix")
+            .arg(prefix)
+            .args(["--query", "[].name", "--output", "tsv"]);
+        Ok(run(c, "Azure list")?
+            .lines()
+            .map(|l| l.trim().to_string())
+            .filter(|l| !l.is_empty())
+            .collect())
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        let mut c = self.command("delete");
+        c.arg("--name").arg(key);
+        run(c, "Azure delete").map(|_| ())
+    }
+
+    fn size(&self, key: &str) -> Result<u64> {
+        let mut c = self.command("show");
+        c.arg("--name")
+            .arg(key)
+            .args(["--query", "properties.contentLength", "--output", "tsv"]);
+        Ok(run(c, "Azure show")?.parse()?)
+    }
+}
+
+struct Sftp {
+    host: String,
+    port: Option<u16>,
+    user: Option<String>,
+    key_path: Option<PathBuf>,
+    dir: String,
+}
+
+impl Sftp {
+    fn path(&self, key: &str) -> String {
+        if self.dir.is_empty() {
+            key.to_string()
+        } else {
+            format!("{}/{}", self.dir, key)
+        }
+    }
+
+    /// Runs `sftp` commands in batch mode, which stops at the first failing
+    /// one unless it is prefixed with `-`. Returns the commands' output.
+    fn batch(&self, commands: &[String], what: &str) -> Result<String> {
+        let mut c = client::tool("sftp");
+        // Never stop to ask for a password or host key confirmation
+        c.args(["-b", "-", "-o", "BatchMode=yes"]);
+        if let Some(port) = self.port {
+            c.arg("-P").arg(port.to_string());
+        }
+        if let Some(key) = &self.key_path {
+            c.arg("-i").arg(key);
+        }
+        match &self.user {
+            Some(user) => c.arg(format!("{}@{}", user, self.host)),
+            None => c.arg(&self.host),
+        };
+        c.stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped());
+
+        let mut child = c.spawn().context("Failed to execute sftp")?;
+        let mut stdin = child.stdin.take().context("Failed to open sftp stdin")?;
+        for command in commands {
+            writeln!(stdin, "{}", command)?;
+        }
+        drop(stdin);
+        let output = child.wait_with_output()?;
+        if !output.status.success() {
+            let err_msg = String::from_utf8_lossy(&output.stderr);
+            anyhow::bail!("{} failed: {}", what, err_msg.trim());
+        }
+        // Batch mode echoes each command as "sftp> ..."
+        Ok(String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter(|l| !l.starts_with("sftp>"))
+            .collect::<Vec<_>>()
+            .join("\n"))
+    }
+}
+
+/// Quotes a path for an sftp batch command.
+fn quote(path: &str) -> String {
+    format!("\"{}\"", path.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+impl RemoteStore for Sftp {
+    fn upload(&self, local: &Path, key: &str) -> Result<()> {
+        let remote = self.path(key);
+        // Create the directories leading up to the file, ignoring ones that exist
+        let dirs: Vec<&Path> = Path::new(&remote)
+            .ancestors()
+            .skip(1)
+            .filter(|d| !d.as_os_str().is_empty() && *d != Path::new("/"))
+            .collect();
+        let mut commands: Vec<String> = dirs
+            .into_iter()
+            .rev()
+            .map(|d| format!("-mkdir {}", quote(&d.to_string_lossy())))
+            .collect();
+        commands.push(format!(
+            "put {} {}",
+            quote(&local.to_string_lossy()),
+            quote(&remote)
+        ));
+        self.batch(&commands, "SFTP upload").map(|_| ())
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<String>> {
+        // A pattern with no matches is an error; `-` lets that mean "nothing"
+        let out = self.batch(
+            &[format!(
+                "-ls -1 {}",
+                quote(&format!("{}*", self.path(prefix)))
+            )],
+            "SFTP list",
+        )?;
+        let root = self.path("");
+        Ok(out
+            .lines()
+            .map(str::trim)
+            .filter(|l| !l.is_empty())
+            .map(|l| l.strip_prefix(root.as_str()).unwrap_or(l).to_string())
+            .collect())
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        self.batch(&[format!("rm {}", quote(&self.path(key)))], "SFTP delete")
+            .map(|_| ())
+    }
+
+    fn size(&self, key: &str) -> Result<u64> {
+        let out = self.batch(&[format!("ls -ln {}", quote(&self.path(key)))], "SFTP ls")?;
+        out.split_whitespace()
+            .nth(4)
+            .and_then(|s| s.parse().ok())
+            .with_context(|| format!("Unexpected SFTP listing for {}: {}", key, out))
+    }
+}
+
+struct S3 {
+    bucket: String,
+    endpoint: Option<String>,
+    region: Option<String>,
+    /// Access key ID and secret; the aws CLI's own lookup is used without them
+    credentials: Option<(String, String)>,
+}
+
+impl S3 {
+    fn command(&self, args: &[&str]) -> Command {
+        let mut c = client::tool("aws");
+        c.args(args);
+        if let Some(endpoint) = &self.endpoint {
+            c.arg("--endpoint-url").arg(endpoint);
+        }
+        if let Some(region) = &self.region {
+            c.arg("--region").arg(region);
+        }
+        if let Some((id, secret)) = &self.credentials {
+            // In the environment rather than on the command line, where `ps` shows it
+            c.env("AWS_ACCESS_KEY_ID", id)
+                .env("AWS_SECRET_ACCESS_KEY", secret)
+                .env_remove("AWS_SESSION_TOKEN")
+                .env_remove("AWS_PROFILE");
+        }
+        c
+    }
+
+    fn url(&self, key: &str) -> String {
+        format!("s3://{}/{}", self.bucket, key)
+    }
+}
+
+impl RemoteStore for S3 {
+    fn upload(&self, local: &Path, key: &str) -> Result<()> {
+        let mut c = self.command(&["s3", "cp", "--only-show-errors"]);
+        c.arg(local).arg(self.url(key));
+        run(c, "S3 upload").map(|_| ())
+    }
+
+    fn list(&self, prefix: &str) -> Result<Vec<String>> {
+        // The CLI fetches every page itself; no objects gives `null`
+        let mut c = self.command(&["s3api", "list-objects-v2"]);
+        c.arg("--bucket")
+            .arg(&self.bucket)
+            .arg("--prefix")
+            .arg(prefix)
+            .args(["--query", "Contents[].Key", "--output", "json"]);
+        let out = run(c, "S3 list")?;
+        let keys: Option<Vec<String>> =
+            serde_json::from_str(&out).context("Unexpected S3 listing")?;
+        Ok(keys.unwrap_or_default())
+    }
+
+    fn delete(&self, key: &str) -> Result<()> {
+        let mut c = self.command(&["s3", "rm", "--only-show-errors"]);
+        c.arg(self.url(key));
+        run(c, "S3 delete").map(|_| ())
+    }
+
+    fn size(&self, key: &str) -> Result<u64> {
+        let mut c = self.command(&["s3api", "head-object"]);
+        c.arg("--bucket")
+            .arg(&self.bucket)
+            .arg("--key")
+            .arg(key)
+            .args(["--query", "ContentLength", "--output", "text"]);
+        Ok(run(c, "S3 head-object")?.parse()?)
+    }
+}
@@ -0,0 +1,302 @@
+use crate::models::{AppConfig, PasswordEncryption};
+use anyhow::{Context, Result};
+use argon2::{Algorithm, Argon2, Params, Version};
+use crypto_box::aead::OsRng;
+use crypto_box::{PublicKey, SecretKey};
+use dialoguer::{theme::ColorfulTheme, Password};
+use log::warn;
+use std::io::IsTerminal;
+use std::sync::{Mutex, OnceLock};
+
+/// Environment variable holding the passphrase, for the daemon and other
+/// runs with no terminal to ask on.
+pub const KEY_ENV: &str = "DB_BACKUP_KEY";
+
+/// Environment variable holding the passphrase `rotate-key` changes to,
+/// when there is no terminal to ask for it on.
+pub const NEW_KEY_ENV: &str = "DB_BACKUP_NEW_KEY";
+
+/// Marks a password stored encrypted; anything else is plaintext from an
+/// older config and is encrypted on the next save.
+const PREFIX: &str = "enc:v1:";
+
+/// Argon2id cost: 19 MiB, two passes, one lane. Pinned rather than taken
+/// from the crate's defaults so existing configs keep deriving the same key.
+const ARGON2_MEMORY_KIB: u32 = 19 * 1024;
+const ARGON2_PASSES: u32 = 2;
+
+/// The key pair for the config's salt, derived once per run.
+fn cached_key() -> &'static Mutex<Option<(PasswordEncryption, SecretKey)>> {
+    static KEY: OnceLock<Mutex<Option<(PasswordEncryption, SecretKey)>>> = OnceLock::new();
+    KEY.get_or_init(|| Mutex::new(None))
+}
+
+/// The loaded config's `password_encryption`, for `reveal`.
+fn loaded_encryption() -> &'static Mutex<Option<PasswordEncryption>> {
+    static ENCRYPTION: OnceLock<Mutex<Option<PasswordEncryption>>> = OnceLock::new();
+    ENCRYPTION.get_or_init(|| Mutex::new(None))
+}
+
+pub fn is_encrypted(value: &str) -> bool {
+    value.starts_with(PREFIX)
+}
+
+/// Every stored password in the config, including remote storage secrets.
+fn passwords(config: &mut AppConfig) -> Vec<&mut String> {
+    config
+        .databases
+        .iter_mut()
+        .flat_map(|db| {
+            [Some(&mut db.connection), db.verify_restore.as_mut()]
+                .into_iter()
+                .flatten()
+                .filter_map(|conn| conn.password.as_mut())
+                .chain(
+                    db.remote
+                        .as_mut()
+                        .and_then(|remote| remote.secret_access_key.as_mut()),
+                )
+        })
+        .collect()
+}
+
+/// Records the loaded config's salt and public key. Its passwords stay
+/// encrypted in memory until `reveal` is asked for one.
+pub fn set_encryption(config: &AppConfig) {
+    *loaded_encryption().lock().unwrap() = config.password_encryption.clone();
+}
+
+/// Decrypts `password` in place if it was stored encrypted, asking for the
+/// passphrase the first time one is needed. Only called on the way to
+/// connecting, so commands that just read the config never ask.
+pub fn reveal(password: &mut Option<String>) -> Result<()> {
+    let Some(sealed) = password.as_deref().and_then(|p| p.strip_prefix(PREFIX)) else {
+        return Ok(());
+    };
+    let encryption = loaded_encryption()
+        .lock()
+        .unwrap()
+        .clone()
+        .context("Config has encrypted passwords but no password_encryption")?;
+    let key = unlock(&encryption)?.with_context(|| {
+        format!(
+            "Config has encrypted passwords; set {} to the passphrase or run in a terminal",
+            KEY_ENV
+        )
+    })?;
+    let plaintext = open(&key, sealed).context("Failed to decrypt a stored password")?;
+    *password = Some(plaintext);
+    Ok(())
+}
+
+/// Encrypts the config's plaintext passwords in place before saving it.
+/// Encrypting only needs the stored public key; a passphrase is needed just
+/// once, to set up a salt the first time. With `setup` (commands that store
+/// passwords) it is asked for, and saving fails without one unless
+/// `plaintext_passwords` opts out. Otherwise it is only taken from
+/// `DB_BACKUP_KEY`, and without it the passwords are kept as they are.
+pub fn encrypt_passwords(config: &mut AppConfig, setup: bool) -> Result<()> {
+    if config.plaintext_passwords || !passwords(config).iter().any(|p| !is_encrypted(p)) {
+        return Ok(());
+    }
+    let encryption = match config.password_encryption.clone() {
+        Some(encryption) => encryption,
+        None => {
+            // Set up by an earlier save in this run, to a copy of the config
+            let cached = cached_key().lock().unwrap().clone();
+            let created = match cached {
+                Some((encryption, _)) => Some(encryption),
+                None => create(setup)?,
+            };
+            let Some(encryption) = created else {
+                if setup {
+                    anyhow::bail!(
+                        "No passphrase to encrypt stored passwords with; set {} or run in a \
+                         terminal, or set plaintext_passwords to store them unencrypted",
+                        KEY_ENV
+                    );
+                }
+                warn!(
+                    "Keeping stored passwords unencrypted; set {} or run `dbr rotate-key` to encrypt them",
+                    KEY_ENV
+                );
+                return Ok(());
+            };
+            config.password_encryption = Some(encryption.clone());
+            encryption
+        }
+    };
+    seal_all(config, &public_key(&encryption)?)
+}
+
+/// Re-encrypts every stored password under a newly chosen passphrase and a
+/// fresh salt, returning how many there are. Passwords still in plaintext
+/// are encrypted too. Nothing is changed unless every password decrypts.
+pub fn rotate(config: &mut AppConfig) -> Result<usize> {
+    if let Some(encryption) = config.password_encryption.clone() {
+        let key = unlock(&encryption)?.with_context(|| {
+            format!(
+                "Set {} to the current passphrase or run in a terminal",
+                KEY_ENV
+            )
+        })?;
+        let decrypted = passwords(config)
+            .into_iter()
+            .map(|password| match password.strip_prefix(PREFIX) {
+                Some(sealed) => open(&key, sealed)
+                    .map(Some)
+                    .context("Failed to decrypt a stored password"),
+                None => Ok(None),
+            })
+            .collect::<Result<Vec<_>>>()?;
+        for (password, plaintext) in passwords(config).into_iter().zip(decrypted) {
+            if let Some(plaintext) = plaintext {
+                *password = plaintext;
+            }
+        }
+    }
+
+    let passphrase = match std::env::var(NEW_KEY_ENV) {
+        Ok(value) => value,
+        Err(_) if std::io::stdin().is_terminal() => Password::with_theme(&ColorfulTheme::default())
+            .with_prompt("New passphrase for stored passwords")
+            .with_confirmation("Repeat passphrase", "Passphrases don't match")
+            .interact()?,
+        Err(_) => anyhow::bail!(
+            "Set {} to the new passphrase or run in a terminal",
+            NEW_KEY_ENV
+        ),
+    };
+    let (encryption, key) = new_key(&passphrase)?;
+    seal_all(config, &key.public_key())?;
+    *cached_key().lock().unwrap() = Some((encryption.clone(), key));
+    config.password_encryption = Some(encryption);
+    Ok(passwords(config).len())
+}
+
+fn seal_all(config: &mut AppConfig, public_key: &PublicKey) -> Result<()> {
+    for password in passwords(config) {
+        if !is_encrypted(password) {
+            *password = format!("{}{}", PREFIX, seal(public_key, password.as_bytes())?);
+        }
+    }
+    Ok(())
+}
+
+/// The passphrase from the environment, or asked for on the terminal.
+/// `None` when neither is available.
+fn passphrase(confirm: bool) -> Result<Option<String>> {
+    if let Ok(value) = std::env::var(KEY_ENV) {
+        return Ok(Some(value));
+    }
+    if !std::io::stdin().is_terminal() {
+        return Ok(None);
+    }
+    let theme = ColorfulTheme::default();
+    let mut prompt = Password::with_theme(&theme);
+    if confirm {
+        prompt = prompt
+            .with_prompt("Passphrase to encrypt stored passwords with")
+            .with_confirmation("Repeat passphrase", "Passphrases don't match");
+    } else {
+        prompt = prompt.with_prompt("Passphrase for stored passwords");
+    }
+    Ok(Some(prompt.interact()?))
+}
+
+/// The secret key for an existing salt, checked against the stored public
+/// key. Holds the cache while asking, so concurrent backups ask only once.
+fn unlock(encryption: &PasswordEncryption) -> Result<Option<SecretKey>> {
+    let mut cached = cached_key().lock().unwrap();
+    if let Some((cached_encryption, key)) = cached.as_ref() {
+        if cached_encryption == encryption {
+            return Ok(Some(key.clone()));
+        }
+    }
+    let Some(passphrase) = passphrase(false)? else {
+        return Ok(None);
+    };
+    let salt = from_hex(&encryption.salt).context("Invalid password_encryption salt")?;
+    let key = derive_key(&passphrase, &salt)?;
+    if to_hex(key.public_key().as_bytes()) != encryption.public_key {
+        anyhow::bail!("Wrong passphrase for stored passwords");
+    }
+    *cached = Some((encryption.clone(), key.clone()));
+    Ok(Some(key))
+}
+
+/// A new salt and key pair from a newly chosen passphrase, asked for if
+/// `ask` and not in `DB_BACKUP_KEY`.
+fn create(ask: bool) -> Result<Option<PasswordEncryption>> {
+    let passphrase = if ask {
+        passphrase(true)?
+    } else {
+        std::env::var(KEY_ENV).ok()
+    };
+    let Some(passphrase) = passphrase else {
+        return Ok(None);
+    };
+    let (encryption, key) = new_key(&passphrase)?;
+    *cached_key().lock().unwrap() = Some((encryption.clone(), key));
+    Ok(Some(encryption))
+}
+
+fn new_key(passphrase: &str) -> Result<(PasswordEncryption, SecretKey)> {
+    let mut salt = [0u8; 16];
+    getrandom::fill(&mut salt).map_err(|e| anyhow::anyhow!("No randomness available: {}", e))?;
+    let key = derive_key(passphrase, &salt)?;
+    let encryption = PasswordEncryption {
+        salt: to_hex(&salt),
+        public_key: to_hex(key.public_key().as_bytes()),
+    };
+    Ok((encryption, key))
+}
+
+fn public_key(encryption: &PasswordEncryption) -> Result<PublicKey> {
+    let bytes: [u8; 32] = from_hex(&encryption.public_key)
+        .and_then(|bytes| bytes.try_into().ok())
+        .context("Invalid password_encryption public_key")?;
+    Ok(PublicKey::from(bytes))
+}
+
+/// Encrypts `plaintext` into a sealed box for `public_key`: X25519 with an
+/// ephemeral key, then XSalsa20-Poly1305. Returns it hex-encoded.
+fn seal(public_key: &PublicKey, plaintext: &[u8]) -> Result<String> {
+    let sealed = public_key
+        .seal(&mut OsRng, plaintext)
+        .map_err(|_| anyhow::anyhow!("Failed to encrypt a password"))?;
+    Ok(to_hex(&sealed))
+}
+
+fn open(key: &SecretKey, sealed: &str) -> Result<String> {
+    let sealed = from_hex(sealed).context("Not valid hex")?;
+    let plaintext = key
+        .unseal(&sealed)
+        .map_err(|_| anyhow::anyhow!("Authentication failed"))?;
+    String::from_utf8(plaintext).context("Not UTF-8")
+}
+
+/// Argon2id over the passphrase, used as an X25519 secret key.
+fn derive_key(passphrase: &str, salt: &[u8]) -> Result<SecretKey> {
+    let params = Params::new(ARGON2_MEMORY_KIB, ARGON2_PASSES, 1, Some(32))
+        .map_err(|e| anyhow::anyhow!("Invalid Argon2 parameters: {}", e))?;
+    let mut key = [0u8; 32];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+        .hash_password_into(passphrase.as_bytes(), salt, &mut key)
+        .map_err(|e| anyhow::anyhow!("Failed to derive the key: {}", e))?;
+    Ok(SecretKey::from(key))
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn from_hex(s: &str) -> Option<Vec<u8>> {
+    if !s.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..i + 2)?, 16).ok())
+        .collect()
+}
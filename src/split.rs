@@ -0,0 +1,202 @@
+use anyhow::{Context, Result};
+use std::fs;
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Writes a stream as `<base>.part001`, `<base>.part002`, ... each at most
+/// `limit` bytes.
+pub struct SplitWriter {
+    base: PathBuf,
+    limit: u64,
+    part: u32,
+    written: u64,
+    current: Option<fs::File>,
+}
+
+impl SplitWriter {
+    pub fn new(base: &Path, limit: u64) -> SplitWriter {
+        SplitWriter {
+            base: base.to_path_buf(),
+            limit: limit.max(1),
+            part: 0,
+            written: 0,
+            current: None,
+        }
+    }
+}
+
+impl Write for SplitWriter {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        if buf.is_empty() {
+            return Ok(0);
+        }
+        if self.current.is_none() || self.written >= self.limit {
+            self.part += 1;
+            self.written = 0;
+            self.current = Some(fs::File::create(part_path(&self.base, self.part))?);
+        }
+
+        let room = (self.limit - self.written).min(buf.len() as u64) as usize;
+        let file = self.current.as_mut().expect("part file is open");
+        let n = file.write(&buf[..room])?;
+        self.written += n as u64;
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self.current.as_mut() {
+            Some(file) => file.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+pub fn part_path(base: &Path, part: u32) -> PathBuf {
+    let mut name = base.as_os_str().to_os_string();
+    name.push(format!(".part{:03}", part));
+    PathBuf::from(name)
+}
+
+/// Strips a `.partNNN` suffix, returning the logical backup file name.
+pub fn logical_name(name: &str) -> &str {
+    match name.rsplit_once(".part") {
+        Some((base, n)) if !n.is_empty() && n.chars().all(|c| c.is_ascii_digit()) => base,
+        _ => name,
+    }
+}
+
+/// Files making up the backup at `path`: the file itself, its parts in
+/// order, or the files of a directory backup.
+pub fn backup_parts(path: &Path) -> Vec<PathBuf> {
+    if path.is_file() {
+        return vec![path.to_path_buf()];
+    }
+    if path.is_dir() {
+        let mut files: Vec<PathBuf> = fs::read_dir(path)
+            .into_iter()
+            .flatten()
+            .filter_map(|e| e.ok())
+            .map(|e| e.path())
+            .filter(|p| p.is_file())
+            .collect();
+        files.sort();
+        return files;
+    }
+    let mut parts = Vec::new();
+    for part in 1.. {
+        let part = part_path(path, part);
+        if !part.is_file() {
+            break;
+        }
+        parts.push(part);
+    }
+    parts
+}
+
+pub fn exists(path: &Path) -> bool {
+    path.is_dir() || !backup_parts(path).is_empty()
+}
+
+/// Reads the raw (still compressed) bytes of a backup, joining parts in order.
+pub fn open_raw(path: &Path) -> Result<Box<dyn Read>> {
+    if path.is_dir() {
+        anyhow::bail!(
+            "{:?} is a directory backup from mydumper; it can only be restored with myloader",
+            path
+        );
+    }
+    let parts = backup_parts(path);
+    if parts.is_empty() {
+        anyhow::bail!("Backup file not found: {:?}", path);
+    }
+    let mut reader: Box<dyn Read> = Box::new(std::io::empty());
+    for part in parts {
+        let file = fs::File::open(&part).with_context(|| format!("Failed to open {:?}", part))?;
+        reader = Box::new(reader.chain(file));
+    }
+    Ok(reader)
+}
+
+pub fn total_size(path: &Path) -> Result<u64> {
+    let mut total = 0;
+    for part in backup_parts(path) {
+        total += fs::metadata(part)?.len();
+    }
+    Ok(total)
+}
+
+/// Moves a backup, part by part when split, to `to`.
+pub fn rename(from: &Path, to: &Path) -> Result<()> {
+    if from.is_file() || from.is_dir() {
+        fs::rename(from, to)?;
+        return Ok(());
+    }
+    for (i, part) in backup_parts(from).into_iter().enumerate() {
+        fs::rename(part, part_path(to, i as u32 + 1))?;
+    }
+    Ok(())
+}
+
+/// Makes `to` share the storage of the backup at `from`, part by part when
+/// split: a hard link, or a copy-on-write clone when `reflink` is set.
+pub fn link(from: &Path, to: &Path, reflink: bool) -> Result<()> {
+    if from.is_dir() {
+        anyhow::bail!("Directory backups can't be linked");
+    }
+    let pairs: Vec<(PathBuf, PathBuf)> = if from.is_file() {
+        vec![(from.to_path_buf(), to.to_path_buf())]
+    } else {
+        backup_parts(from)
+            .into_iter()
+            .enumerate()
+            .map(|(i, part)| (part, part_path(to, i as u32 + 1)))
+            .collect()
+    };
+
+    let mut linked = Vec::new();
+    for (source, target) in pairs {
+        let result = if reflink {
+            clone_file(&source, &target)
+        } else {
+            fs::hard_link(&source, &target).map_err(Into::into)
+        };
+        if let Err(e) = result {
+            for done in linked {
+                fs::remove_file(done).ok();
+            }
+            return Err(e);
+        }
+        linked.push(target);
+    }
+    Ok(())
+}
+
+/// Reflinks `from` to `to` with `cp`, which fails rather than copying when
+/// the filesystem has no copy-on-write support.
+fn clone_file(from: &Path, to: &Path) -> Result<()> {
+    let output = std::process::Command::new("cp")
+        .arg("--reflink=always")
+        .arg(from)
+        .arg(to)
+        .output()
+        .context("Failed to execute cp")?;
+    if !output.status.success() {
+        fs::remove_file(to).ok();
+        anyhow::bail!(
+            "Reflink not supported: {}",
+            String::from_utf8_lossy(&output.stderr).trim()
+        );
+    }
+    Ok(())
+}
+
+pub fn remove(path: &Path) -> Result<()> {
+    if path.is_dir() {
+        fs::remove_dir_all(path)?;
+        return Ok(());
+    }
+    for part in backup_parts(path) {
+        fs::remove_file(part)?;
+    }
+    Ok(())
+}
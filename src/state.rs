@@ -0,0 +1,97 @@
+use chrono::{DateTime, Local};
+use serde::{Deserialize, Serialize};
+use std::collections::{BTreeMap, BTreeSet};
+
+/// Runtime state persisted between invocations, keyed by config name.
+#[derive(Debug, Serialize, Deserialize, Default)]
+pub struct AppState {
+    #[serde(default)]
+    pub databases: BTreeMap<String, DbState>,
+    /// Configs whose latest `run` failed, for `run --retry-failed`
+    #[serde(default)]
+    pub failed_runs: BTreeSet<String>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct DbState {
+    #[serde(default)]
+    pub stats: BackupStats,
+    /// Latest schedule fire time already handled by the daemon or `daemon --oneshot`
+    #[serde(default)]
+    pub last_scheduled_run: Option<DateTime<Local>>,
+    /// Latest backup whose data was uploaded to remote storage, for
+    /// `remote.deduplicate`
+    #[serde(default)]
+    pub last_upload: Option<RemoteUpload>,
+}
+
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct RemoteUpload {
+    /// Object key of the backup, without any `.partNNN` suffix
+    pub key: String,
+    pub content_hash: String,
+}
+
+/// Running totals across every backup attempt for one config.
+#[derive(Debug, Serialize, Deserialize, Default, Clone)]
+pub struct BackupStats {
+    pub backups: u64,
+    pub failures: u64,
+    pub bytes_written: u64,
+    pub last_success: Option<DateTime<Local>>,
+    pub last_failure: Option<DateTime<Local>>,
+    /// Latest backup discarded as identical to the previous one, which
+    /// still shows the schedule is being kept
+    #[serde(default)]
+    pub last_unchanged: Option<DateTime<Local>>,
+}
+
+impl AppState {
+    pub fn entry(&mut self, name: &str) -> &mut DbState {
+        self.databases.entry(name.to_string()).or_default()
+    }
+
+    /// Drops everything recorded for configs not in `names`, returning the
+    /// names that were removed.
+    pub fn retain_configs(&mut self, names: &BTreeSet<&str>) -> Vec<String> {
+        let mut removed: BTreeSet<String> = BTreeSet::new();
+        self.databases.retain(|name, _| {
+            let keep = names.contains(name.as_str());
+            if !keep {
+                removed.insert(name.clone());
+            }
+            keep
+        });
+        self.failed_runs.retain(|name| {
+            let keep = names.contains(name.as_str());
+            if !keep {
+                removed.insert(name.clone());
+            }
+            keep
+        });
+        removed.into_iter().collect()
+    }
+}
+
+impl BackupStats {
+    pub fn record_success(&mut self, bytes: u64) {
+        self.backups += 1;
+        self.bytes_written += bytes;
+        self.last_success = Some(Local::now());
+    }
+
+    pub fn record_failure(&mut self) {
+        self.failures += 1;
+        self.last_failure = Some(Local::now());
+    }
+}
+
+/// Written by a running daemon so `daemon-status` can report on it.
+#[derive(Debug, Serialize, Deserialize, Clone)]
+pub struct DaemonStatus {
+    pub pid: u32,
+    pub started_at: DateTime<Local>,
+    pub updated_at: DateTime<Local>,
+    pub backups: u64,
+    pub failures: u64,
+}
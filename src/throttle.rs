@@ -0,0 +1,38 @@
+use std::io::Read;
+use std::time::{Duration, Instant};
+
+/// Reads no faster than `bytes_per_sec` on average by sleeping between
+/// reads. Wrapped around a dump's stdout, this fills the pipe so the dump
+/// tool blocks on writing and in turn reads the database more slowly.
+pub struct RateLimited<R> {
+    inner: R,
+    bytes_per_sec: u64,
+    started: Instant,
+    read: u64,
+}
+
+impl<R> RateLimited<R> {
+    pub fn new(inner: R, bytes_per_sec: u64) -> RateLimited<R> {
+        RateLimited {
+            inner,
+            bytes_per_sec: bytes_per_sec.max(1),
+            started: Instant::now(),
+            read: 0,
+        }
+    }
+}
+
+impl<R: Read> Read for RateLimited<R> {
+    fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+        // Small reads keep the rate smooth rather than bursting a whole buffer
+        let chunk = (self.bytes_per_sec / 10).clamp(1, buf.len() as u64) as usize;
+        let n = self.inner.read(&mut buf[..chunk])?;
+        self.read += n as u64;
+
+        let due = Duration::from_secs_f64(self.read as f64 / self.bytes_per_sec as f64);
+        if let Some(ahead) = due.checked_sub(self.started.elapsed()) {
+            std::thread::sleep(ahead);
+        }
+        Ok(n)
+    }
+}
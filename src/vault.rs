@@ -0,0 +1,74 @@
+use crate::client;
+use crate::models::{ConnectionDetails, VaultSecret};
+use anyhow::{Context, Result};
+use serde_json::{Map, Value};
+use std::collections::HashMap;
+use std::sync::{Mutex, OnceLock};
+use std::time::{Duration, Instant};
+
+/// How long a secret read from Vault is reused, so a run over many configs
+/// sharing one secret asks for it once while a long-running daemon still
+/// picks up rotated credentials.
+const CACHE_TTL: Duration = Duration::from_secs(300);
+
+type Cache = Mutex<HashMap<String, (Instant, Map<String, Value>)>>;
+
+fn cache() -> &'static Cache {
+    static CACHE: OnceLock<Cache> = OnceLock::new();
+    CACHE.get_or_init(|| Mutex::new(HashMap::new()))
+}
+
+/// Fills in `conn`'s password, and user if configured, from Vault. The
+/// values only live in memory; nothing read here is written to the config.
+pub fn apply(secret: &VaultSecret, conn: &mut ConnectionDetails) -> Result<()> {
+    let fields = read(&secret.path)?;
+    let field = |name: &str| -> Result<String> {
+        match fields.get(name) {
+            Some(Value::String(s)) => Ok(s.clone()),
+            Some(other) => Ok(other.to_string()),
+            None => anyhow::bail!("Vault secret {} has no field '{}'", secret.path, name),
+        }
+    };
+    conn.password = Some(field(&secret.password_field)?);
+    if let Some(user_field) = &secret.user_field {
+        conn.user = field(user_field)?;
+    }
+    Ok(())
+}
+
+/// The key/value pairs stored at `path`, from the cache if fresh enough.
+fn read(path: &str) -> Result<Map<String, Value>> {
+    if let Ok(cache) = cache().lock() {
+        if let Some((fetched, fields)) = cache.get(path) {
+            if fetched.elapsed() < CACHE_TTL {
+                return Ok(fields.clone());
+            }
+        }
+    }
+
+    let output = client::tool("vault")
+        .args(["kv", "get", "-format=json"])
+        .arg(path)
+        .output()
+        .context("Failed to execute vault")?;
+    if !output.status.success() {
+        let err_msg = String::from_utf8_lossy(&output.stderr);
+        anyhow::bail!("Failed to read {} from Vault: {}", path, err_msg.trim());
+    }
+    let response: Value = serde_json::from_slice(&output.stdout)
+        .with_context(|| format!("Unexpected response from vault for {}", path))?;
+    // KV v2 nests the secret under data.data, v1 has it directly under data
+    let data = &response["data"];
+    let fields = match data.get("data") {
+        Some(Value::Object(fields)) => fields.clone(),
+        _ => data
+            .as_object()
+            .cloned()
+            .with_context(|| format!("Vault returned no data for {}", path))?,
+    };
+
+    if let Ok(mut cache) = cache().lock() {
+        cache.insert(path.to_string(), (Instant::now(), fields.clone()));
+    }
+    Ok(fields)
+}